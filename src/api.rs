@@ -0,0 +1,46 @@
+use crate::config::validate::ConfigIssue;
+use crate::models::{AppState, ProfileQuery, Role, TokenQuery};
+use crate::sockets::presence::ClientInfo;
+use axum::{
+    Json,
+    extract::{Extension, Query},
+    http::StatusCode,
+};
+use std::sync::Arc;
+
+/// Everyone attached to a terminal (`?profile=`, else the default one); needs a writer or
+/// viewer token when tokens are configured.
+pub async fn list_clients(
+    Query(params): Query<TokenQuery>,
+    Query(profile): Query<ProfileQuery>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<Vec<ClientInfo>>, StatusCode> {
+    let role = state.auth.read().unwrap().role(params.token.as_deref());
+    role.ok_or(StatusCode::UNAUTHORIZED)?;
+    let term = state.terminals.get(profile.name()).await.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(term.clients.list()))
+}
+
+/// Writes a partial config into config.toml, like the `set-config` message; needs a writer token
+/// when tokens are configured. Clients see the change through their `config` event.
+pub async fn update_config(
+    Query(params): Query<TokenQuery>,
+    Extension(state): Extension<Arc<AppState>>,
+    Json(update): Json<serde_json::Value>,
+) -> Result<StatusCode, (StatusCode, Json<Vec<ConfigIssue>>)> {
+    let role = state.auth.read().unwrap().role(params.token.as_deref());
+    match role {
+        Some(Role::Write) => {}
+        Some(Role::View) => return Err((StatusCode::FORBIDDEN, Json(Vec::new()))),
+        None => return Err((StatusCode::UNAUTHORIZED, Json(Vec::new()))),
+    }
+    match state.watcher.update(&update).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(issues) => Err((StatusCode::UNPROCESSABLE_ENTITY, Json(issues))),
+    }
+}
+
+/// The JSON Schema of config.toml. Public, so editors can fetch it from a `#:schema` line.
+pub async fn config_schema(Extension(state): Extension<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(state.schema.clone())
+}
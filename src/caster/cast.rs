@@ -1,9 +1,11 @@
+use super::sink::{CastSink, FileSink, StdoutSink};
 use crate::models::{buf_trim, logger};
-use base64::Engine as _;
+use anyhow::Context;
 use std::sync::Arc;
 use std::{
-    fs::OpenOptions,
+    fs::{File, OpenOptions},
     io::{BufWriter, Write},
+    path::PathBuf,
     time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::{
@@ -11,22 +13,36 @@ use tokio::{
     time::{self, Duration},
 };
 use unsigned_varint::encode as varint;
-use zstd::stream::encode_all;
 
-const HEARTBEAT_FN: &str = "heartbeat.log";
+pub(crate) const HEARTBEAT_FN: &str = "heartbeat.log";
 
-#[derive(Clone, Copy, Debug)]
-enum EventKind {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
     Input,
     Output,
     Resize,
+    Marker,
+}
+
+impl TryFrom<u8> for EventKind {
+    type Error = u8;
+
+    fn try_from(v: u8) -> Result<Self, u8> {
+        match v {
+            0 => Ok(EventKind::Input),
+            1 => Ok(EventKind::Output),
+            2 => Ok(EventKind::Resize),
+            3 => Ok(EventKind::Marker),
+            x => Err(x),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct RawEvt {
-    elapsed: f32,
-    kind: EventKind,
-    payload: Vec<u8>,
+    pub elapsed: f32,
+    pub kind: EventKind,
+    pub payload: Vec<u8>,
 }
 
 fn encode_evt(e: &RawEvt) -> Vec<u8> {
@@ -36,7 +52,7 @@ fn encode_evt(e: &RawEvt) -> Vec<u8> {
     v.push(e.kind as u8);
 
     let mut len_buf = [0u8; 5];
-    if matches!(e.kind, EventKind::Input | EventKind::Output) {
+    if matches!(e.kind, EventKind::Input | EventKind::Output | EventKind::Marker) {
         let var = varint::u32(e.payload.len() as u32, &mut len_buf);
         v.extend_from_slice(var);
     }
@@ -44,14 +60,122 @@ fn encode_evt(e: &RawEvt) -> Vec<u8> {
     v
 }
 
-fn write_binary(file: &mut BufWriter<std::fs::File>, bytes: &[u8]) -> std::io::Result<()> {
-    file.write_all(bytes)?;
-    file.flush()
+/// What the caster writes to. `--sink`s are fixed; the cast file and stdout follow
+/// `log_level` and can be switched on and off while running.
+struct Sinks {
+    log_dir: PathBuf,
+    start: std::time::Instant,
+    timestamp: u128,
+    extra: Vec<Box<dyn CastSink>>,
+    // created on first use and kept, so switching back on appends without a second header
+    file: Option<FileSink>,
+    stdout: Option<StdoutSink>,
+    log_level: u8,
+    hb_file: Option<BufWriter<File>>,
+    // rows, cols; every stream states it right after its header
+    size: (u16, u16),
+}
+
+impl Sinks {
+    /// Session header followed by the current size, so replays of a stream started late have the right size.
+    fn header(&self) -> Vec<u8> {
+        let (rows, cols) = self.size;
+        let mut header = self.timestamp.to_le_bytes().to_vec();
+        header.extend(encode_evt(&RawEvt {
+            elapsed: self.start.elapsed().as_secs_f32(),
+            kind: EventKind::Resize,
+            payload: [rows.to_le_bytes(), cols.to_le_bytes()].concat(),
+        }));
+        header
+    }
+
+    fn open_file(&mut self) -> anyhow::Result<()> {
+        let mut file = FileSink::create(&self.log_dir, self.timestamp).context("create cast file")?;
+        file.write(&self.header());
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Turning recording on while running only logs when the cast file cannot be created.
+    fn set_log_level(&mut self, log_level: u8) {
+        self.log_level = log_level;
+        if log_level >= 1
+            && self.file.is_none()
+            && let Err(e) = self.open_file()
+        {
+            logger("error", format!("Failed to create cast file: {:#}", e));
+        }
+        if log_level >= 2 && self.stdout.is_none() && !self.extra.iter().any(|s| s.is_stdout()) {
+            let mut stdout = StdoutSink::new(self.timestamp);
+            stdout.write(&self.header());
+            self.stdout = Some(stdout);
+        }
+        if log_level < 2
+            && let Some(stdout) = &mut self.stdout
+        {
+            stdout.flush();
+        }
+    }
+
+    fn active(&mut self) -> impl Iterator<Item = &mut dyn CastSink> {
+        let file = self.file.as_mut().filter(|_| self.log_level >= 1);
+        let stdout = self.stdout.as_mut().filter(|_| self.log_level >= 2);
+        self.extra
+            .iter_mut()
+            .map(|s| s.as_mut() as &mut dyn CastSink)
+            .chain(file.map(|s| s as &mut dyn CastSink))
+            .chain(stdout.map(|s| s as &mut dyn CastSink))
+    }
+
+    fn is_recording(&self) -> bool {
+        !self.extra.is_empty() || self.log_level >= 1
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for sink in self.active() {
+            sink.write(bytes);
+        }
+    }
+
+    fn flush(&mut self) {
+        for sink in self.active() {
+            sink.flush();
+        }
+    }
+
+    fn heartbeat(&mut self, ts: u32) {
+        if !self.is_recording() {
+            return;
+        }
+        if self.hb_file.is_none() {
+            let opened = std::fs::create_dir_all(&self.log_dir).and_then(|_| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.log_dir.join(HEARTBEAT_FN))
+            });
+            match opened {
+                Ok(f) => self.hb_file = Some(BufWriter::new(f)),
+                Err(e) => logger("error", format!("Failed to open heartbeat log: {}", e)),
+            }
+        }
+        if let Some(hb) = &mut self.hb_file {
+            hb.write_all(&ts.to_le_bytes()).ok();
+            hb.flush().ok();
+        }
+    }
+}
+
+/// Settings changed while running.
+enum Ctl {
+    LogLevel(u8),
+    FlushInterval(u32),
 }
 
 pub struct Caster {
     cast_tx: mpsc::UnboundedSender<RawEvt>,
     hb_tx: mpsc::UnboundedSender<u32>,
+    ctl_tx: mpsc::UnboundedSender<Ctl>,
 }
 
 impl Caster {
@@ -59,77 +183,85 @@ impl Caster {
         log_dir: std::path::PathBuf,
         start: std::time::Instant,
         timestamp: u128,
-        verbose_log: bool,
-        verbose_interval: u32,
+        sinks: Vec<Box<dyn CastSink>>,
+        log_level: u8,
+        flush_interval: u32,
         stty_size: (u16, u16), // rows, cols
     ) -> anyhow::Result<Arc<Self>> {
-        if log_dir.exists() && !log_dir.is_dir() {
-            anyhow::bail!("'{}' exists and is not a directory", log_dir.display());
-        }
-        std::fs::create_dir_all(&log_dir)?;
-
-        let cast_path = log_dir.join(format!("{}.cast", timestamp));
-        let hb_path = log_dir.join(HEARTBEAT_FN);
-
-        let cast_file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&cast_path)?);
-        let hb_file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&hb_path)?);
-
         let (cast_tx, mut cast_rx) = mpsc::unbounded_channel::<RawEvt>();
         let (hb_tx, mut hb_rx) = mpsc::unbounded_channel::<u32>();
+        let (ctl_tx, mut ctl_rx) = mpsc::unbounded_channel::<Ctl>();
 
-        tokio::spawn(async move {
-            let mut cast_file = cast_file;
-            let mut hb_file = hb_file;
+        let mut sinks = Sinks {
+            log_dir,
+            start,
+            timestamp,
+            extra: sinks,
+            file: None,
+            stdout: None,
+            log_level,
+            hb_file: None,
+            size: stty_size,
+        };
+        let header = sinks.header();
+        for sink in sinks.extra.iter_mut() {
+            sink.write(&header);
+        }
+        // a cast file that cannot be created at startup is fatal, not just logged
+        if log_level >= 1 {
+            sinks.open_file()?;
+        }
+        sinks.set_log_level(log_level);
 
+        tokio::spawn(async move {
             let mut buf_disk: Vec<u8> = Vec::new();
-            let mut buf_stdout: Vec<u8> = Vec::new();
 
             let mut flush_disk = time::interval(Duration::from_millis(10));
             flush_disk.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
 
-            let mut flush_stdout = time::interval(Duration::from_secs(verbose_interval.into()));
-            flush_stdout.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+            let mut flush_sinks = time::interval(Duration::from_secs(flush_interval.into()));
+            flush_sinks.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
 
             // skip the first tick
             flush_disk.tick().await;
-            flush_stdout.tick().await;
-            write_binary(&mut cast_file, &timestamp.to_le_bytes()).ok();
-            if verbose_log {
-                buf_stdout.extend_from_slice(&timestamp.to_le_bytes());
-            }
-
-            let (mut rows, mut cols) = stty_size;
+            flush_sinks.tick().await;
 
             loop {
                 tokio::select! {
                     Some(evt) = cast_rx.recv() => {
                         match evt.kind {
-                            EventKind::Input => {
-                                let bytes = encode_evt(&evt);
-                                write_binary(&mut cast_file, &bytes).ok();
+                            EventKind::Input | EventKind::Marker => {
+                                sinks.write(&encode_evt(&evt));
                             }
                             EventKind::Resize => {
-                                let bytes = encode_evt(&evt);
-                                write_binary(&mut cast_file, &bytes).ok();
-                                rows = u16::from_le_bytes([evt.payload[0], evt.payload[1]]);
-                                cols = u16::from_le_bytes([evt.payload[2], evt.payload[3]]);
-                                if verbose_log {
-                                    buf_stdout.extend_from_slice(&bytes);
-                                }
+                                sinks.write(&encode_evt(&evt));
+                                sinks.size = (
+                                    u16::from_le_bytes([evt.payload[0], evt.payload[1]]),
+                                    u16::from_le_bytes([evt.payload[2], evt.payload[3]]),
+                                );
                             }
                             EventKind::Output => {
-                                buf_disk.extend_from_slice(evt.payload.as_slice());
+                                if sinks.is_recording() {
+                                    buf_disk.extend_from_slice(evt.payload.as_slice());
+                                }
                             }
                         }
                     }
 
-                    Some(ts)  = hb_rx.recv() => {
-                        hb_file.write_all(&ts.to_le_bytes()).unwrap();
-                        hb_file.flush().ok();
-                    }
+                    Some(ts)  = hb_rx.recv() => sinks.heartbeat(ts),
+
+                    Some(ctl) = ctl_rx.recv() => match ctl {
+                        Ctl::LogLevel(level) => sinks.set_log_level(level),
+                        Ctl::FlushInterval(secs) => {
+                            flush_sinks = time::interval(Duration::from_secs(secs.into()));
+                            flush_sinks.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                            flush_sinks.tick().await;
+                        }
+                    },
 
                     _ = flush_disk.tick() => {
                         if !buf_disk.is_empty() {
+                            let (rows, cols) = sinks.size;
                             let idx = buf_trim(&buf_disk, cols, rows as u32 + 20);
                             let trimmed = &buf_disk[idx..];
                             let evt = RawEvt {
@@ -137,39 +269,32 @@ impl Caster {
                                 kind: EventKind::Output,
                                 payload: trimmed.to_vec(),
                             };
-                            let bytes = encode_evt(&evt);
-                            write_binary(&mut cast_file, &bytes).ok();
-                            if verbose_log {
-                                buf_stdout.extend_from_slice(&bytes);
-                            }
+                            sinks.write(&encode_evt(&evt));
                             buf_disk.clear();
                         }
                     }
-                    _ = flush_stdout.tick(), if verbose_log => {
-                        if !buf_stdout.is_empty() {
-                            match encode_all(&buf_stdout[..], 3) {
-                                Ok(cmp) => {
-                                    let b64 = base64::engine::general_purpose::STANDARD.encode(&cmp);
-                                    let payload = serde_json::json!([timestamp, b64]);
-                                    logger("cast", payload);
-                                }
-                                Err(e) => {
-                                    logger("error", serde_json::json!(format!("Error encoding cast data: {}", e.to_string())));
-                                }
-                            }
-                            buf_stdout.clear();
-                        }
-                    }
+                    _ = flush_sinks.tick() => sinks.flush(),
 
                     else => break,
                 }
             }
 
-            let _ = cast_file.flush();
-            let _ = hb_file.flush();
+            sinks.flush();
+            if let Some(hb) = &mut sinks.hb_file {
+                let _ = hb.flush();
+            }
         });
 
-        Ok(Arc::new(Self { cast_tx, hb_tx }))
+        Ok(Arc::new(Self { cast_tx, hb_tx, ctl_tx }))
+    }
+
+    /// 0 = no cast file, 1 = cast file, 2 = cast file and stdout; `--sink`s are unaffected.
+    pub fn set_log_level(&self, log_level: u8) {
+        self.ctl_tx.send(Ctl::LogLevel(log_level)).ok();
+    }
+
+    pub fn set_flush_interval(&self, secs: u32) {
+        self.ctl_tx.send(Ctl::FlushInterval(secs)).ok();
     }
 
     pub fn input(&self, elapsed: f32, bytes: Vec<u8>) {
@@ -202,6 +327,15 @@ impl Caster {
             })
             .ok();
     }
+    pub fn marker(&self, elapsed: f32, label: &str) {
+        self.cast_tx
+            .send(RawEvt {
+                elapsed,
+                kind: EventKind::Marker,
+                payload: label.as_bytes().to_vec(),
+            })
+            .ok();
+    }
     pub fn heartbeat(&self) {
         let ts_sec = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -0,0 +1,87 @@
+use super::cast::{EventKind, RawEvt};
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use unsigned_varint::decode as varint;
+
+pub struct Recording {
+    pub timestamp: u128, // unix millis
+    pub events: Vec<RawEvt>,
+}
+
+impl Recording {
+    pub fn start_secs(&self) -> f64 {
+        self.timestamp as f64 / 1000.0
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.events.last().map(|e| e.elapsed).unwrap_or(0.0)
+    }
+}
+
+/// Decodes as many whole events as `buf` holds.
+/// Returns the events and the number of bytes consumed; a truncated tail is left untouched.
+pub fn decode_events(buf: &[u8]) -> Result<(Vec<RawEvt>, usize)> {
+    let mut events = Vec::new();
+    let mut pos = 0;
+
+    while buf.len() - pos >= 5 {
+        let rest = &buf[pos..];
+        let elapsed = f32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let kind = match EventKind::try_from(rest[4]) {
+            Ok(k) => k,
+            Err(x) => bail!("unknown event kind {x} at offset {pos}"),
+        };
+        let body = &rest[5..];
+
+        let (payload, tail) = match kind {
+            EventKind::Resize => {
+                if body.len() < 4 {
+                    break;
+                }
+                body.split_at(4)
+            }
+            EventKind::Input | EventKind::Output | EventKind::Marker => {
+                let (len, body) = match varint::u32(body) {
+                    Ok(x) => x,
+                    Err(unsigned_varint::decode::Error::Insufficient) => break,
+                    Err(e) => bail!("bad length at offset {pos}: {e}"),
+                };
+                if body.len() < len as usize {
+                    break;
+                }
+                body.split_at(len as usize)
+            }
+        };
+
+        events.push(RawEvt {
+            elapsed,
+            kind,
+            payload: payload.to_vec(),
+        });
+        pos = buf.len() - tail.len();
+    }
+
+    Ok((events, pos))
+}
+
+pub fn parse_cast(buf: &[u8]) -> Result<Recording> {
+    if buf.len() < 16 {
+        bail!("cast data too short for header");
+    }
+    let timestamp = u128::from_le_bytes(buf[..16].try_into().unwrap());
+    let (events, _) = decode_events(&buf[16..])?;
+    Ok(Recording { timestamp, events })
+}
+
+pub fn read_cast(path: &Path) -> Result<Recording> {
+    let buf = std::fs::read(path).with_context(|| format!("read {:?}", path))?;
+    parse_cast(&buf).with_context(|| format!("decode {:?}", path))
+}
+
+pub fn read_heartbeats(path: &Path) -> Result<Vec<u32>> {
+    let buf = std::fs::read(path).with_context(|| format!("read {:?}", path))?;
+    Ok(buf
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
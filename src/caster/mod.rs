@@ -1,2 +1,7 @@
 pub mod cast;
+pub mod decode;
+pub mod render;
+pub mod report;
+pub mod sink;
+pub mod stream;
 pub use cast::Caster;
@@ -0,0 +1,273 @@
+use super::cast::EventKind;
+use super::decode::{Recording, read_cast};
+use crate::models::{AppConfig, Theme, load_builtin_themes};
+use anyhow::{Context, Result, bail};
+use std::{fmt::Write as _, path::Path};
+
+const FONT_SIZE: f32 = 14.0;
+const CELL_W: f32 = FONT_SIZE * 0.6;
+const CELL_H: f32 = FONT_SIZE * 1.2;
+const PAD: f32 = 8.0;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum RenderFormat {
+    Svg,
+    Text,
+}
+
+pub struct RenderOpts {
+    pub format: RenderFormat,
+    pub theme: Theme,
+    pub fps: f32,
+    pub max_idle: f32,
+    pub size: (u16, u16), // rows, cols before the first resize
+}
+
+struct Frame {
+    at: f32,
+    rows: u16,
+    cols: u16,
+    svg: String,
+}
+
+/// Plays the recording through a terminal emulator, calling `on_event` after every event.
+fn replay(rec: &Recording, size: (u16, u16), mut on_event: impl FnMut(f32, EventKind, &[u8], &vt100::Screen)) {
+    let mut parser = vt100::Parser::new(size.0, size.1, 0);
+    for e in &rec.events {
+        match e.kind {
+            EventKind::Output => parser.process(&e.payload),
+            EventKind::Resize => {
+                let rows = u16::from_le_bytes([e.payload[0], e.payload[1]]);
+                let cols = u16::from_le_bytes([e.payload[2], e.payload[3]]);
+                parser.screen_mut().set_size(rows, cols);
+            }
+            EventKind::Input | EventKind::Marker => {}
+        }
+        on_event(e.elapsed, e.kind, &e.payload, parser.screen());
+    }
+}
+
+fn clock(t: f32) -> String {
+    format!("{:02}:{:04.1}", (t / 60.0) as u32, t % 60.0)
+}
+
+pub fn render_text(rec: &Recording, size: (u16, u16)) -> String {
+    let mut out = String::new();
+    let mut last = (0.0, String::new());
+
+    replay(rec, size, |at, kind, payload, screen| {
+        if kind == EventKind::Marker {
+            let label = String::from_utf8_lossy(payload);
+            let _ = writeln!(
+                out,
+                "=== [{}] {} ===\n{}\n",
+                clock(at),
+                label,
+                screen.contents().trim_end()
+            );
+        }
+        last = (at, screen.contents());
+    });
+    let _ = writeln!(out, "=== [{}] end ===\n{}", clock(last.0), last.1.trim_end());
+    out
+}
+
+fn color(c: vt100::Color, theme: &Theme, default: &str) -> String {
+    match c {
+        vt100::Color::Default => default.to_string(),
+        vt100::Color::Idx(i) if i < 16 => theme.ansi()[i as usize].to_string(),
+        vt100::Color::Idx(i) if i < 232 => {
+            let i = i - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            format!("#{:02x}{:02x}{:02x}", level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        vt100::Color::Idx(i) => {
+            let v = 8 + (i - 232) * 10;
+            format!("#{:02x}{:02x}{:02x}", v, v, v)
+        }
+        vt100::Color::Rgb(r, g, b) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+    }
+}
+
+/// Resolved (foreground, background, bold) of a cell.
+fn style(cell: &vt100::Cell, theme: &Theme) -> (String, String, bool) {
+    let fg = color(cell.fgcolor(), theme, theme.fg());
+    let bg = color(cell.bgcolor(), theme, theme.bg());
+    match cell.inverse() {
+        true => (bg, fg, cell.bold()),
+        false => (fg, bg, cell.bold()),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// One screen as an SVG group: background runs as rects, text runs as tspans.
+fn screen_svg(screen: &vt100::Screen, theme: &Theme) -> String {
+    let (rows, cols) = screen.size();
+    let mut bg = String::new();
+    let mut fg = String::new();
+
+    for row in 0..rows {
+        let y = PAD + row as f32 * CELL_H;
+        let _ = write!(fg, r#"<text y="{:.1}">"#, y + FONT_SIZE);
+        let mut col = 0;
+        while col < cols {
+            let Some(cell) = screen.cell(row, col) else {
+                break;
+            };
+            let (f, b, bold) = style(cell, theme);
+
+            // extend the run while the style stays the same
+            let start = col;
+            let mut text = String::new();
+            while let Some(c) = screen.cell(row, col).filter(|_| col < cols) {
+                if style(c, theme) != (f.clone(), b.clone(), bold) {
+                    break;
+                }
+                if !c.is_wide_continuation() {
+                    text.push_str(if c.has_contents() { c.contents() } else { " " });
+                }
+                col += 1;
+            }
+
+            let x = PAD + start as f32 * CELL_W;
+            if b != theme.bg() {
+                let _ = write!(
+                    bg,
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                    x,
+                    y,
+                    (col - start) as f32 * CELL_W,
+                    CELL_H,
+                    b
+                );
+            }
+            if !text.trim().is_empty() {
+                let weight = if bold { r#" font-weight="bold""# } else { "" };
+                let _ = write!(
+                    fg,
+                    r#"<tspan x="{:.1}" fill="{}"{}>{}</tspan>"#,
+                    x,
+                    f,
+                    weight,
+                    escape(&text)
+                );
+            }
+        }
+        fg.push_str("</text>");
+    }
+
+    if !screen.hide_cursor() {
+        let (r, c) = screen.cursor_position();
+        let _ = write!(
+            bg,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" opacity="0.7"/>"#,
+            PAD + c as f32 * CELL_W,
+            PAD + r as f32 * CELL_H,
+            CELL_W,
+            CELL_H,
+            theme.cursor()
+        );
+    }
+    bg + &fg
+}
+
+pub fn render_svg(rec: &Recording, opts: &RenderOpts) -> String {
+    let min_gap = 1.0 / opts.fps;
+    let mut frames: Vec<Frame> = Vec::new();
+    // playback clock with long pauses squeezed to `max_idle`
+    let (mut clock, mut prev) = (0.0f32, 0.0f32);
+
+    replay(rec, opts.size, |at, kind, _, screen| {
+        clock += (at - prev).min(opts.max_idle);
+        prev = at;
+        if !matches!(kind, EventKind::Output | EventKind::Resize) {
+            return;
+        }
+        let (rows, cols) = screen.size();
+        let svg = screen_svg(screen, &opts.theme);
+        match frames.last_mut() {
+            Some(last) if last.svg == svg => {}
+            Some(last) if clock - last.at < min_gap => {
+                *last = Frame {
+                    at: last.at,
+                    rows,
+                    cols,
+                    svg,
+                }
+            }
+            _ => frames.push(Frame {
+                at: clock,
+                rows,
+                cols,
+                svg,
+            }),
+        }
+    });
+
+    let total = frames.last().map_or(0.0, |f| f.at) + opts.max_idle.max(1.0);
+    let rows = frames.iter().map(|f| f.rows).max().unwrap_or(opts.size.0);
+    let cols = frames.iter().map(|f| f.cols).max().unwrap_or(opts.size.1);
+    let (w, h) = (cols as f32 * CELL_W + 2.0 * PAD, rows as f32 * CELL_H + 2.0 * PAD);
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.1} {h:.1}" font-family="monospace" font-size="{FONT_SIZE}" xml:space="preserve"><rect width="100%" height="100%" fill="{}"/>"#,
+        opts.theme.bg()
+    );
+    for (i, f) in frames.iter().enumerate() {
+        let end = frames.get(i + 1).map_or(total, |n| n.at);
+        let _ = write!(
+            out,
+            r#"<g visibility="hidden"><animate attributeName="visibility" calcMode="discrete" values="hidden;visible;hidden" keyTimes="0;{:.5};{:.5}" dur="{:.3}s" repeatCount="indefinite"/>{}</g>"#,
+            f.at / total,
+            end / total,
+            total,
+            f.svg
+        );
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Picks the theme the frontend would show: an explicit name, else the one in config.toml.
+/// Themes defined in config.toml come before built-in ones.
+pub fn resolve_theme(name: Option<String>, config_path: &Path, resource: Option<&Path>) -> Result<Theme> {
+    let cfg = std::fs::read_to_string(config_path)
+        .ok()
+        .and_then(|txt| toml::from_str::<AppConfig>(&txt).ok())
+        .unwrap_or_default();
+    // an SVG has no color scheme to follow; light/dark pairs render dark
+    let name = name.unwrap_or_else(|| cfg.theme.pick(true).to_string());
+    if let Some(theme) = cfg.themes.get(&name) {
+        return Ok(theme.clone());
+    }
+    if name == "Default" {
+        return Ok(Theme::default());
+    }
+    let Some(resource) = resource else {
+        bail!(
+            "theme '{name}' needs --resource to locate {}",
+            crate::models::THEMES_FILE
+        );
+    };
+    load_builtin_themes(resource)?
+        .remove(&name)
+        .with_context(|| format!("unknown theme '{name}'"))
+}
+
+pub fn run(input: &Path, out: Option<&Path>, opts: RenderOpts) -> Result<()> {
+    let rec = read_cast(input)?;
+    let rendered = match opts.format {
+        RenderFormat::Text => render_text(&rec, opts.size),
+        RenderFormat::Svg => render_svg(&rec, &opts),
+    };
+    match out {
+        Some(path) => std::fs::write(path, rendered).with_context(|| format!("write {:?}", path))?,
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
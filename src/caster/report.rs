@@ -0,0 +1,278 @@
+use super::cast::{EventKind, HEARTBEAT_FN};
+use super::decode::{Recording, read_cast, read_heartbeats};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+// the client pings every 10s; allow one missed beat before calling it a disconnect
+const HEARTBEAT_GAP: f64 = 25.0;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IdlePeriod {
+    pub start: f64, // seconds since session start
+    pub secs: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Marker {
+    pub at: f64, // seconds since session start
+    pub label: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    pub session: String,
+    pub start: f64, // unix seconds
+    pub duration_secs: f64,
+    pub connected_secs: f64,
+    pub active_secs: f64,
+    pub keystrokes: usize,
+    pub keystrokes_per_min: f64,
+    pub resizes: usize,
+    pub idle: Vec<IdlePeriod>,
+    pub commands: Vec<String>,
+    pub markers: Vec<Marker>,
+}
+
+/// Collapses sorted points into spans, joining neighbours closer than `gap`.
+fn spans(points: &[f64], gap: f64) -> Vec<(f64, f64)> {
+    let mut out: Vec<(f64, f64)> = Vec::new();
+    for &t in points {
+        match out.last_mut() {
+            Some(last) if t - last.1 <= gap => last.1 = t,
+            _ => out.push((t, t)),
+        }
+    }
+    out
+}
+
+fn overlap(a: &[(f64, f64)], from: f64, to: f64) -> f64 {
+    a.iter().map(|&(s, e)| (e.min(to) - s.max(from)).max(0.0)).sum()
+}
+
+/// Key presses in one input event: a character each, an escape sequence (arrow keys,
+/// function keys) once, and a bracketed paste once however long it is.
+fn key_presses(payload: &[u8]) -> usize {
+    const PASTE_START: &[u8] = b"\x1b[200~";
+    const PASTE_END: &[u8] = b"\x1b[201~";
+    let mut keys = 0;
+    let mut i = 0;
+    while i < payload.len() {
+        let rest = &payload[i..];
+        i += if rest.starts_with(PASTE_START) {
+            rest.windows(PASTE_END.len())
+                .position(|w| w == PASTE_END)
+                .map_or(rest.len(), |end| end + PASTE_END.len())
+        } else if let [0x1b, b'[' | b'O', tail @ ..] = rest {
+            2 + tail
+                .iter()
+                .position(|b| (0x40..=0x7e).contains(b))
+                .map_or(tail.len(), |end| end + 1)
+        } else {
+            // one utf-8 character: a lead byte and its continuation bytes
+            1 + rest[1..]
+                .iter()
+                .take_while(|&&b| b & 0b1100_0000 == 0b1000_0000)
+                .count()
+        };
+        keys += 1;
+    }
+    keys
+}
+
+/// Replays line editing on raw input to recover the submitted command lines.
+/// Anything the shell completes on its own (tab, history) is invisible here.
+fn commands(input: &[u8]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i] {
+            b'\r' | b'\n' => {
+                let cmd = String::from_utf8_lossy(&line).trim().to_string();
+                if !cmd.is_empty() {
+                    out.push(cmd);
+                }
+                line.clear();
+            }
+            0x7f | 0x08 => {
+                // drop a whole utf-8 sequence
+                while let Some(b) = line.pop() {
+                    if b & 0b1100_0000 != 0b1000_0000 {
+                        break;
+                    }
+                }
+            }
+            0x03 | 0x15 => line.clear(), // ^C, ^U
+            0x1b => {
+                // skip CSI / SS3 sequences (arrows, function keys)
+                i += 1;
+                if i < input.len() && matches!(input[i], b'[' | b'O') {
+                    i += 1;
+                    while i < input.len() && !(0x40..=0x7e).contains(&input[i]) {
+                        i += 1;
+                    }
+                }
+            }
+            b if b >= 0x20 => line.push(b),
+            _ => {}
+        }
+        i += 1;
+    }
+    out
+}
+
+pub fn session_report(name: String, rec: &Recording, heartbeats: &[u32], idle_gap: f64) -> SessionReport {
+    let start = rec.start_secs();
+    let duration = rec.duration() as f64;
+
+    let mut inputs = Vec::new();
+    let mut keystrokes = 0;
+    let mut raw_input = Vec::new();
+    let mut resizes = 0;
+    let mut markers = Vec::new();
+    for e in &rec.events {
+        match e.kind {
+            EventKind::Input => {
+                inputs.push(e.elapsed as f64);
+                keystrokes += key_presses(&e.payload);
+                raw_input.extend_from_slice(&e.payload);
+            }
+            EventKind::Resize => resizes += 1,
+            EventKind::Marker => markers.push(Marker {
+                at: e.elapsed as f64,
+                label: String::from_utf8_lossy(&e.payload).into_owned(),
+            }),
+            EventKind::Output => {}
+        }
+    }
+
+    let beats: Vec<f64> = heartbeats
+        .iter()
+        .map(|&t| t as f64 - start)
+        .filter(|&t| t >= -HEARTBEAT_GAP && t <= duration + HEARTBEAT_GAP)
+        .collect();
+    let connected = spans(&beats, HEARTBEAT_GAP);
+    let connected_secs = overlap(&connected, 0.0, duration);
+
+    // typing bursts count as active; when heartbeats exist, only while a tab was open
+    let typing = spans(&inputs, idle_gap);
+    let active_secs = if connected.is_empty() {
+        overlap(&typing, 0.0, duration)
+    } else {
+        typing.iter().map(|&(s, e)| overlap(&connected, s, e)).sum()
+    };
+
+    let idle = typing
+        .windows(2)
+        .map(|w| IdlePeriod {
+            start: w[0].1,
+            secs: w[1].0 - w[0].1,
+        })
+        .collect();
+
+    let keystrokes_per_min = if active_secs > 0.0 {
+        keystrokes as f64 / (active_secs / 60.0)
+    } else {
+        0.0
+    };
+
+    SessionReport {
+        session: name,
+        start,
+        duration_secs: duration,
+        connected_secs,
+        active_secs,
+        keystrokes,
+        keystrokes_per_min,
+        resizes,
+        idle,
+        commands: commands(&raw_input),
+        markers,
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_csv(reports: &[SessionReport]) {
+    println!(
+        "session,start,duration_secs,connected_secs,active_secs,keystrokes,keystrokes_per_min,resizes,idle_periods,idle_secs,commands,markers"
+    );
+    for r in reports {
+        println!(
+            "{},{:.3},{:.1},{:.1},{:.1},{},{:.1},{},{},{:.1},{},{}",
+            csv_field(&r.session),
+            r.start,
+            r.duration_secs,
+            r.connected_secs,
+            r.active_secs,
+            r.keystrokes,
+            r.keystrokes_per_min,
+            r.resizes,
+            r.idle.len(),
+            r.idle.iter().map(|p| p.secs).sum::<f64>(),
+            r.commands.len(),
+            r.markers.len(),
+        );
+    }
+}
+
+fn cast_files(log_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(log_dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|x| x == "cast"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+pub fn run(log_dir: &Path, files: Vec<PathBuf>, format: ReportFormat, idle_gap: f64) -> Result<()> {
+    let files = if files.is_empty() { cast_files(log_dir)? } else { files };
+    let heartbeats = read_heartbeats(&log_dir.join(HEARTBEAT_FN)).unwrap_or_default();
+
+    let mut reports = Vec::with_capacity(files.len());
+    for path in files {
+        let rec = read_cast(&path)?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        reports.push(session_report(name, &rec, &heartbeats, idle_gap));
+    }
+
+    match format {
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&reports)?),
+        ReportFormat::Csv => write_csv(&reports),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_key_presses() {
+        let cases: &[(&str, &[u8], usize)] = &[
+            ("letters", b"ls\r", 3),
+            ("multibyte", "é€".as_bytes(), 2),
+            ("arrow keys", b"\x1b[A\x1bOB", 2),
+            ("modified arrow", b"\x1b[1;5Dx", 2),
+            ("paste", b"\x1b[200~echo hi\r\x1b[201~\r", 2),
+            ("lone escape", b"\x1b", 1),
+        ];
+        for (name, payload, want) in cases {
+            assert_eq!(key_presses(payload), *want, "{name}");
+        }
+    }
+}
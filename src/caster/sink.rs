@@ -0,0 +1,300 @@
+use crate::models::logger;
+use anyhow::{Context, bail};
+use base64::Engine as _;
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::UnixStream,
+    sync::mpsc,
+    time::{self, Duration},
+};
+use zstd::stream::encode_all;
+
+const SPOOL_EXT: &str = "zst";
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// Destination for encoded cast events.
+/// `write` receives the event stream in order, starting with the 16-byte session header;
+/// `flush` is called every `verbose_interval` so batching sinks can ship what they hold.
+pub trait CastSink: Send {
+    fn write(&mut self, bytes: &[u8]);
+    fn flush(&mut self) {}
+    // a `--sink stdout` stands in for the stdout stream of log_level 2, so there is only one
+    fn is_stdout(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SinkSpec {
+    Stdout,
+    Unix(PathBuf),
+    Http(String),
+}
+
+impl FromStr for SinkSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "stdout" {
+            Ok(SinkSpec::Stdout)
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            Ok(SinkSpec::Unix(PathBuf::from(path)))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(SinkSpec::Http(s.to_string()))
+        } else {
+            bail!("unknown sink '{s}' (expected stdout, unix:<path> or http(s)://<url>)")
+        }
+    }
+}
+
+pub fn build_sink(spec: &SinkSpec, timestamp: u128, spool_dir: &Path) -> anyhow::Result<Box<dyn CastSink>> {
+    Ok(match spec {
+        SinkSpec::Stdout => Box::new(StdoutSink::new(timestamp)),
+        SinkSpec::Unix(path) => Box::new(RemoteSink::spawn(Remote::Unix(path.clone()), timestamp, spool_dir)?),
+        SinkSpec::Http(url) => Box::new(RemoteSink::spawn(Remote::Http(url.clone()), timestamp, spool_dir)?),
+    })
+}
+
+fn compress(buf: &[u8]) -> Option<Vec<u8>> {
+    match encode_all(buf, 3) {
+        Ok(cmp) => Some(cmp),
+        Err(e) => {
+            logger("error", format!("Error encoding cast data: {}", e));
+            None
+        }
+    }
+}
+
+/// Line in the `["cast", [timestamp, base64(zstd(events))]]` format shared by stdout and unix sockets.
+fn cast_line(timestamp: u128, cmp: &[u8]) -> serde_json::Value {
+    let b64 = base64::engine::general_purpose::STANDARD.encode(cmp);
+    serde_json::json!(["cast", [timestamp, b64]])
+}
+
+// local cast file
+pub struct FileSink {
+    file: BufWriter<std::fs::File>,
+}
+
+impl FileSink {
+    pub fn create(log_dir: &Path, timestamp: u128) -> anyhow::Result<Self> {
+        if log_dir.exists() && !log_dir.is_dir() {
+            anyhow::bail!("'{}' exists and is not a directory", log_dir.display());
+        }
+        std::fs::create_dir_all(log_dir)?;
+
+        let path = log_dir.join(format!("{}.cast", timestamp));
+        let file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&path)?);
+        Ok(Self { file })
+    }
+}
+
+impl CastSink for FileSink {
+    fn write(&mut self, bytes: &[u8]) {
+        self.file.write_all(bytes).ok();
+        self.file.flush().ok();
+    }
+}
+
+// zstd+base64 json lines on stdout
+pub struct StdoutSink {
+    timestamp: u128,
+    buf: Vec<u8>,
+}
+
+impl StdoutSink {
+    pub fn new(timestamp: u128) -> Self {
+        Self {
+            timestamp,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl CastSink for StdoutSink {
+    fn write(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn flush(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        if let Some(cmp) = compress(&self.buf) {
+            // println! holds the stdout lock for the whole line, like logger
+            println!("{}", cast_line(self.timestamp, &cmp));
+        }
+        self.buf.clear();
+    }
+
+    fn is_stdout(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Remote {
+    Unix(PathBuf),
+    Http(String),
+}
+
+struct Chunk {
+    seq: u64,
+    data: Vec<u8>, // zstd compressed
+}
+
+impl Remote {
+    async fn send(&self, client: &reqwest::Client, timestamp: u128, chunk: &Chunk) -> anyhow::Result<()> {
+        match self {
+            Remote::Unix(path) => {
+                let mut line = cast_line(timestamp, &chunk.data).to_string().into_bytes();
+                line.push(b'\n');
+                let mut sock = UnixStream::connect(path)
+                    .await
+                    .with_context(|| format!("connect {:?}", path))?;
+                sock.write_all(&line).await?;
+                sock.shutdown().await?;
+            }
+            Remote::Http(url) => {
+                client
+                    .post(url)
+                    .header("content-type", "application/zstd")
+                    .header("x-cast-session", timestamp.to_string())
+                    .header("x-cast-seq", chunk.seq.to_string())
+                    .body(chunk.data.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ships compressed chunks to a unix socket or HTTP collector.
+/// Chunks that cannot be delivered are spooled to disk and retried with exponential backoff,
+/// including ones left over from earlier runs.
+pub struct RemoteSink {
+    buf: Vec<u8>,
+    seq: u64,
+    tx: mpsc::UnboundedSender<Chunk>,
+}
+
+impl RemoteSink {
+    fn spawn(remote: Remote, timestamp: u128, spool_root: &Path) -> anyhow::Result<Self> {
+        let spool = spool_root.join(spool_name(&remote));
+        std::fs::create_dir_all(&spool).with_context(|| format!("create spool {:?}", spool))?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<Chunk>();
+
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut backoff = BACKOFF_MIN;
+            let mut retry = time::interval(backoff);
+            retry.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    Some(chunk) = rx.recv() => {
+                        // keep ordering: anything already spooled goes out first
+                        if spool_is_empty(&spool) && remote.send(&client, timestamp, &chunk).await.is_ok() {
+                            continue;
+                        }
+                        if let Err(e) = spool_chunk(&spool, timestamp, &chunk) {
+                            logger("error", format!("Failed to spool cast chunk: {}", e));
+                        }
+                    }
+
+                    _ = retry.tick() => {
+                        match drain_spool(&spool, &remote, &client).await {
+                            Ok(()) => backoff = BACKOFF_MIN,
+                            Err(e) => {
+                                backoff = (backoff * 2).min(BACKOFF_MAX);
+                                logger("warn", format!("Cast sink {:?} unavailable, retrying in {:?}: {}", remote, backoff, e));
+                            }
+                        }
+                        retry = time::interval_at(time::Instant::now() + backoff, backoff);
+                        retry.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                    }
+
+                    else => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            buf: Vec::new(),
+            seq: 0,
+            tx,
+        })
+    }
+}
+
+impl CastSink for RemoteSink {
+    fn write(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn flush(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        if let Some(data) = compress(&self.buf) {
+            self.tx.send(Chunk { seq: self.seq, data }).ok();
+            self.seq += 1;
+        }
+        self.buf.clear();
+    }
+}
+
+fn spool_name(remote: &Remote) -> String {
+    let raw = match remote {
+        Remote::Unix(p) => format!("unix-{}", p.display()),
+        Remote::Http(u) => u.clone(),
+    };
+    raw.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn spool_is_empty(spool: &Path) -> bool {
+    std::fs::read_dir(spool).map(|mut d| d.next().is_none()).unwrap_or(true)
+}
+
+// <timestamp>-<seq>.zst, zero-padded so lexical order is delivery order
+fn spool_chunk(spool: &Path, timestamp: u128, chunk: &Chunk) -> std::io::Result<()> {
+    let path = spool.join(format!("{:020}-{:010}.{}", timestamp, chunk.seq, SPOOL_EXT));
+    std::fs::write(path, &chunk.data)
+}
+
+async fn drain_spool(spool: &Path, remote: &Remote, client: &reqwest::Client) -> anyhow::Result<()> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(spool)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|x| x == SPOOL_EXT))
+        .collect();
+    files.sort();
+
+    for path in files {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let Some((ts, seq)) = stem
+            .split_once('-')
+            .and_then(|(a, b)| Some((a.parse().ok()?, b.parse().ok()?)))
+        else {
+            continue;
+        };
+        let chunk = Chunk {
+            seq,
+            data: tokio::fs::read(&path).await?,
+        };
+        remote.send(client, ts, &chunk).await?;
+        tokio::fs::remove_file(&path).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use super::decode::decode_events;
+use anyhow::{Context, Result};
+use base64::Engine as _;
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/// Pulls the `[timestamp, base64(zstd(events))]` payload out of a `["cast", ...]` log line.
+/// Other log kinds and unparsable lines yield `None`.
+fn cast_chunk(line: &str) -> Option<(u128, String)> {
+    let (kind, payload): (String, (u128, String)) = serde_json::from_str(line).ok()?;
+    (kind == "cast").then_some(payload)
+}
+
+/// Reassembles the per-session event streams from a server log, keyed by session timestamp.
+pub fn collect_sessions(input: impl Read) -> Result<BTreeMap<u128, Vec<u8>>> {
+    let mut sessions: BTreeMap<u128, Vec<u8>> = BTreeMap::new();
+
+    for (n, line) in BufReader::new(input).lines().enumerate() {
+        let line = line?;
+        let Some((ts, b64)) = cast_chunk(&line) else {
+            continue;
+        };
+        let cmp = base64::engine::general_purpose::STANDARD
+            .decode(b64)
+            .with_context(|| format!("line {}: bad base64", n + 1))?;
+        let raw = zstd::stream::decode_all(&cmp[..]).with_context(|| format!("line {}: bad zstd frame", n + 1))?;
+
+        let buf = sessions.entry(ts).or_default();
+        // the first chunk of a session carries the header; if the log starts mid-session, synthesize it
+        if buf.is_empty() && !raw.starts_with(&ts.to_le_bytes()) {
+            buf.extend_from_slice(&ts.to_le_bytes());
+        }
+        buf.extend_from_slice(&raw);
+    }
+    Ok(sessions)
+}
+
+pub fn run(input: Option<PathBuf>, out_dir: &Path) -> Result<()> {
+    let sessions = match &input {
+        Some(path) => {
+            let file = std::fs::File::open(path).with_context(|| format!("open {:?}", path))?;
+            collect_sessions(file)?
+        }
+        None => collect_sessions(std::io::stdin().lock())?,
+    };
+
+    std::fs::create_dir_all(out_dir)?;
+    for (ts, mut buf) in sessions {
+        // drop a partial trailing event, e.g. when the log was cut off
+        let (events, used) = decode_events(&buf[16..]).with_context(|| format!("session {ts}"))?;
+        buf.truncate(16 + used);
+
+        let path = out_dir.join(format!("{}.cast", ts));
+        std::fs::write(&path, &buf).with_context(|| format!("write {:?}", path))?;
+        println!("{} ({} events)", path.display(), events.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use super::validate::{ConfigIssue, Severity, parse_cfg};
+use crate::models::AppConfig;
+use serde_json::{Map, Value as Json};
+use toml_edit::{Array, DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+/// Merges a partial config from a client into the text of config.toml, keeping comments,
+/// formatting and every key the update does not mention. `null` removes a key, so it falls
+/// back to its default. Returns the new text only if it still loads cleanly.
+pub fn merge_update(txt: &str, update: &Json) -> Result<String, Vec<ConfigIssue>> {
+    let Json::Object(fields) = update else {
+        return Err(vec![ConfigIssue::error("a config update must be an object")]);
+    };
+    if fields.contains_key("server") {
+        return Err(vec![ConfigIssue::error(
+            "[server] can only be changed in the file itself",
+        )]);
+    }
+
+    // unknown keys in the file are left alone, but an update should only name real ones
+    let mut unknown = Vec::new();
+    let checked = without_nulls(update);
+    if let Err(e) = serde_ignored::deserialize::<_, _, AppConfig>(&checked, |path| unknown.push(path.to_string())) {
+        return Err(vec![ConfigIssue::error(e.to_string())]);
+    }
+    if !unknown.is_empty() {
+        return Err(unknown
+            .iter()
+            .map(|path| ConfigIssue::error(format!("unknown key '{path}'")))
+            .collect());
+    }
+
+    let mut doc: DocumentMut = txt.parse().map_err(|e| {
+        vec![ConfigIssue::error(format!(
+            "config.toml does not parse, fix it by hand first: {e}"
+        ))]
+    })?;
+    merge(doc.as_table_mut(), fields).map_err(|e| vec![e])?;
+    let out = doc.to_string();
+
+    match parse_cfg(&out) {
+        (Some(_), _) => Ok(out),
+        (None, issues) => Err(issues.into_iter().filter(|i| i.severity == Severity::Error).collect()),
+    }
+}
+
+fn merge(table: &mut dyn TableLike, fields: &Map<String, Json>) -> Result<(), ConfigIssue> {
+    for (key, update) in fields {
+        match update {
+            Json::Null => {
+                table.remove(key);
+            }
+            Json::Object(inner) => match table.get_mut(key).and_then(Item::as_table_like_mut) {
+                Some(existing) => merge(existing, inner)?,
+                None => {
+                    let mut new = Table::new();
+                    merge(&mut new, inner)?;
+                    table.insert(key, Item::Table(new));
+                }
+            },
+            _ => {
+                let mut value = to_value(update)?;
+                // keep the old value's surrounding whitespace and trailing comment
+                if let Some(Item::Value(old)) = table.get_mut(key) {
+                    *value.decor_mut() = old.decor().clone();
+                    *old = value;
+                } else {
+                    table.insert(key, Item::Value(value));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_value(json: &Json) -> Result<Value, ConfigIssue> {
+    Ok(match json {
+        Json::Null => return Err(ConfigIssue::error("null is only allowed as a whole value")),
+        Json::Bool(b) => Value::from(*b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::from(i),
+            None => Value::from(n.as_f64().unwrap_or_default()),
+        },
+        Json::String(s) => Value::from(s.as_str()),
+        Json::Array(items) => Value::Array(items.iter().map(to_value).collect::<Result<Array, _>>()?),
+        Json::Object(fields) => {
+            let mut table = InlineTable::new();
+            for (key, item) in fields {
+                table.insert(key, to_value(item)?);
+            }
+            Value::InlineTable(table)
+        }
+    })
+}
+
+fn without_nulls(json: &Json) -> Json {
+    match json {
+        Json::Object(fields) => Json::Object(
+            fields
+                .iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FILE: &str = r#"# my settings
+theme = "Default"
+scrollback = 1000 # lines
+
+[profiles.py]
+command = "python3"
+env = { A = "1" }
+
+[server]
+port = 8080
+"#;
+
+    #[test]
+    fn merges_and_keeps_formatting() {
+        let cases = [
+            (
+                "replaces a value, keeping its comment",
+                json!({"scrollback": 5000}),
+                FILE.replace("scrollback = 1000 # lines", "scrollback = 5000 # lines"),
+            ),
+            (
+                "null removes a key",
+                json!({"scrollback": null}),
+                FILE.replace("scrollback = 1000 # lines\n", ""),
+            ),
+            (
+                "null removes a whole table",
+                json!({"profiles": {"py": null}}),
+                FILE.replace("[profiles.py]\ncommand = \"python3\"\nenv = { A = \"1\" }\n\n", ""),
+            ),
+            (
+                "merges into a nested table",
+                json!({"profiles": {"py": {"cwd": "/tmp"}}}),
+                FILE.replace("env = { A = \"1\" }\n", "env = { A = \"1\" }\ncwd = \"/tmp\"\n"),
+            ),
+            (
+                "appends a new key",
+                json!({"word_separators": " ,"}),
+                FILE.replace(
+                    "scrollback = 1000 # lines\n",
+                    "scrollback = 1000 # lines\nword_separators = \" ,\"\n",
+                ),
+            ),
+        ];
+        for (name, update, want) in cases {
+            assert_eq!(merge_update(FILE, &update).as_deref(), Ok(want.as_str()), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_updates() {
+        let cases = [
+            ("not an object", json!(["scrollback"]), "must be an object"),
+            ("server", json!({"server": {"port": 1}}), "[server]"),
+            ("unknown key", json!({"scrolback": 1}), "unknown key 'scrolback'"),
+            (
+                "unknown nested key",
+                json!({"profiles": {"py": {"shell": "sh"}}}),
+                "unknown key",
+            ),
+            ("wrong type", json!({"scrollback": "lots"}), "invalid type"),
+        ];
+        for (name, update, want) in cases {
+            let issues = merge_update(FILE, &update).expect_err(name);
+            assert!(issues.iter().any(|i| i.message.contains(want)), "{name}: {issues:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_a_file_that_does_not_parse() {
+        let issues = merge_update("theme = ", &json!({"scrollback": 1})).unwrap_err();
+        assert!(issues[0].message.contains("fix it by hand"), "{issues:?}");
+    }
+}
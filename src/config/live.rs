@@ -0,0 +1,128 @@
+use super::server::{clap_message, reparse};
+use super::validate::{ConfigIssue, Severity};
+use crate::models::{AppState, AuthOpts, KeepaliveOpts, logger};
+use clap::{ArgMatches, Command};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `[server]` settings applied without a restart; changes to any other key are reported instead.
+/// log_level, verbose_interval and the tokens are only read from the file with --trust-config-file.
+const LIVE: &[&str] = &[
+    "history_limit",
+    "log_level",
+    "verbose_interval",
+    "token",
+    "view_tokens",
+    "ping_interval",
+    "client_timeout",
+];
+
+fn raw(m: &ArgMatches, id: &str) -> Vec<String> {
+    m.get_raw(id)
+        .map(|vals| vals.map(|v| v.to_string_lossy().into_owned()).collect())
+        .unwrap_or_default()
+}
+
+/// Follows the `[server]` table of the config file, applying what can change live.
+pub fn spawn_server_reload(state: Arc<AppState>, cmd: Command, startup: ArgMatches) {
+    let mut cfg_rx = state.watcher.subscribe();
+    let mut table = cfg_rx.borrow_and_update().server.clone();
+    tokio::spawn(async move {
+        let mut current = startup.clone();
+        while cfg_rx.changed().await.is_ok() {
+            let next = cfg_rx.borrow_and_update().server.clone();
+            if next == table {
+                continue;
+            }
+            table = next;
+
+            let (matches, locked) = match reparse(cmd.clone(), &table) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    let msg = match e.downcast_ref::<clap::Error>() {
+                        Some(e) => clap_message(e),
+                        None => e.to_string(),
+                    };
+                    logger("error", format!("[server] not applied: {}", msg));
+                    state
+                        .watcher
+                        .set_server_issues(vec![ConfigIssue::error(format!("[server]: {msg}"))]);
+                    continue;
+                }
+            };
+
+            for id in LIVE.iter().filter(|id| raw(&current, id) != raw(&matches, id)) {
+                apply(&state, &matches, id).await;
+                let shown = if id.contains("token") {
+                    vec!["<redacted>".into()]
+                } else {
+                    raw(&matches, id)
+                };
+                logger("info", format!("Applied server.{} = {:?}", id, shown));
+            }
+
+            let restart = cmd
+                .get_arguments()
+                .map(|a| a.get_id().as_str())
+                .filter(|id| !LIVE.contains(id) && raw(&startup, id) != raw(&matches, id))
+                .map(|id| format!("server.{id} changed; restart the server to apply it"));
+            let issues: Vec<ConfigIssue> = locked
+                .into_iter()
+                .chain(restart)
+                .map(|msg| {
+                    logger("warn", &msg);
+                    ConfigIssue {
+                        severity: Severity::Warning,
+                        message: msg,
+                        line: None,
+                        column: None,
+                    }
+                })
+                .collect();
+            state.watcher.set_server_issues(issues);
+            current = matches;
+        }
+    });
+}
+
+async fn apply(state: &AppState, m: &ArgMatches, id: &str) {
+    match id {
+        "history_limit" => {
+            if let Some(&limit) = m.get_one::<usize>(id) {
+                state.terminals.set_history_limit(limit).await;
+            }
+        }
+        "log_level" => {
+            if let Some(&level) = m.get_one::<u8>(id) {
+                state.terminals.set_log_level(level).await;
+            }
+        }
+        "verbose_interval" => {
+            if let Some(&secs) = m.get_one::<u32>(id) {
+                state.terminals.set_flush_interval(secs).await;
+            }
+        }
+        "token" | "view_tokens" => {
+            *state.auth.write().unwrap() = AuthOpts {
+                token: m.get_one::<String>("token").cloned(),
+                view_tokens: m
+                    .get_many::<String>("view_tokens")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect(),
+            };
+        }
+        "ping_interval" | "client_timeout" => {
+            let secs = |id| m.get_one::<u32>(id).copied().map(u64::from).unwrap_or_default();
+            let opts = KeepaliveOpts {
+                interval: Duration::from_secs(secs("ping_interval")),
+                timeout: Duration::from_secs(secs("client_timeout")),
+            };
+            state
+                .keepalive
+                .send_if_modified(|cur| std::mem::replace(cur, opts.clone()) != opts);
+        }
+        _ => {}
+    }
+}
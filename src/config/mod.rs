@@ -1,2 +1,7 @@
+pub mod edit;
+pub mod live;
+pub mod schema;
+pub mod server;
+pub mod validate;
 pub mod watcher;
 pub use watcher::{ConfigWatcher, spawn_cfg_watcher};
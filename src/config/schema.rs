@@ -0,0 +1,85 @@
+use super::server;
+use crate::models::layout::builtin_layouts;
+use crate::models::{AppConfig, load_builtin_themes};
+use clap::Command;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::path::Path;
+
+// Placeholders in the derived schema, filled in by `config_schema` with what is only known at runtime.
+
+/// A built-in theme or one from `themes`.
+pub struct ThemeName;
+
+/// A built-in layout or one from `layouts`.
+pub struct LayoutName;
+
+/// The `[server]` table: command line flags.
+pub struct ServerTable;
+
+impl JsonSchema for ThemeName {
+    fn schema_name() -> Cow<'static, str> {
+        "ThemeName".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({ "type": "string" })
+    }
+}
+
+impl JsonSchema for LayoutName {
+    fn schema_name() -> Cow<'static, str> {
+        "LayoutName".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({ "type": "string" })
+    }
+}
+
+impl JsonSchema for ServerTable {
+    fn schema_name() -> Cow<'static, str> {
+        "ServerTable".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({ "type": "object" })
+    }
+}
+
+/// The JSON Schema of config.toml, for editors that take one (`#:schema <url>` on the first line).
+/// Theme and layout names offer the built-in ones but accept any name, since the file may define its own.
+pub fn config_schema(cmd: &Command, themes: &[String]) -> Value {
+    let mut schema = schemars::schema_for!(AppConfig).to_value();
+    schema["title"] = json!("xterm-rs config.toml");
+    let defs = &mut schema["$defs"];
+    defs["ThemeName"] = name_schema("A built-in theme or one from `themes`", themes);
+    let layouts: Vec<String> = builtin_layouts().map(String::from).collect();
+    defs["LayoutName"] = name_schema("A built-in layout or one from `layouts`", &layouts);
+    defs["ServerTable"] = server::schema(cmd);
+    // the colors `validate` accepts
+    if let Some(Value::Object(colors)) = defs["Theme"].get_mut("properties") {
+        for color in colors.values_mut() {
+            color["pattern"] = json!("^#([0-9a-fA-F]{3}|[0-9a-fA-F]{6}|[0-9a-fA-F]{8})$");
+        }
+    }
+    schema
+}
+
+/// "Default" and the themes shipped in `resource`; just "Default" without one.
+pub fn builtin_theme_names(resource: Option<&Path>) -> anyhow::Result<Vec<String>> {
+    let mut names = vec!["Default".to_string()];
+    if let Some(resource) = resource {
+        names.extend(load_builtin_themes(resource)?.into_keys());
+    }
+    Ok(names)
+}
+
+fn name_schema(description: &str, known: &[String]) -> Value {
+    json!({
+        "description": description,
+        "type": "string",
+        "anyOf": [{ "enum": known }, { "minLength": 1 }],
+    })
+}
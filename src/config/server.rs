@@ -0,0 +1,344 @@
+use anyhow::{Context, Result};
+use clap::{ArgMatches, Command, parser::ValueSource};
+use serde_json::json;
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Prefix of the environment variables that set server flags, e.g. `XTERM_RS_PORT`.
+pub const ENV_PREFIX: &str = "XTERM_RS_";
+
+// flags that make no sense in a file or the environment
+const NOT_LAYERED: &[&str] = &["help", "version", "config_path", "print_config"];
+
+// what `[server]` may set without --trust-config-file: config.toml usually belongs to the student,
+// so recording, access and what runs in the terminal stay with the command line and environment
+const FILE_SETTABLE: &[&str] = &[
+    "rows",
+    "cols",
+    "port",
+    "history_limit",
+    "max_fps",
+    "max_frame_size",
+    "skip_behind",
+    "ping_interval",
+    "client_timeout",
+    "size_policy",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::File => "file",
+            Source::Env => "env",
+            Source::Cli => "cli",
+        }
+    }
+}
+
+/// Where each server setting's effective value came from, by arg id.
+pub type Sources = BTreeMap<String, Source>;
+
+/// Adds an `XTERM_RS_<NAME>` variable to every layered top-level flag.
+pub fn with_env(cmd: Command) -> Command {
+    cmd.mut_args(|arg| {
+        let id = arg.get_id().as_str();
+        if NOT_LAYERED.contains(&id) || arg.is_positional() {
+            return arg;
+        }
+        let var = format!("{}{}", ENV_PREFIX, id.to_uppercase());
+        arg.env(var)
+    })
+}
+
+/// Parses the command line with the `[server]` table of config.toml slotted in as defaults < file < env < CLI.
+/// The file is found through `--config-path` (or its env var), which is not itself layered.
+/// Problems in the file never stop the server: each bad key is skipped, a file that does not parse
+/// leaves just defaults, env and CLI, and the returned messages say what was ignored.
+pub fn layered_matches(cmd: Command) -> Result<(ArgMatches, Sources, Vec<String>)> {
+    layered_matches_from(cmd, std::env::args_os().collect())
+}
+
+fn layered_matches_from(cmd: Command, argv: Vec<OsString>) -> Result<(ArgMatches, Sources, Vec<String>)> {
+    let first = cmd
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&argv)
+        .unwrap_or_else(|e| e.exit());
+    if first.subcommand().is_some() {
+        return Ok((cmd.get_matches_from(argv), Sources::new(), Vec::new()));
+    }
+
+    let config_path = first
+        .get_raw("config_path")
+        .and_then(|mut v| v.next())
+        .map(Path::new)
+        .context("no config path")?;
+    let (table, mut skipped) = read_server_table(config_path);
+    // only the command line or environment can be wrong here
+    let (matches, sources, ignored) = merge(cmd, argv, &table).unwrap_or_else(|e| e.exit());
+    skipped.extend(ignored);
+    Ok((matches, sources, skipped))
+}
+
+/// Applies a changed `[server]` table over the same command line and environment as at startup.
+/// Also returns a message for each key that was skipped.
+pub fn reparse(cmd: Command, table: &toml::Table) -> Result<(ArgMatches, Vec<String>)> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let (matches, _, skipped) = merge(cmd, argv, table)?;
+    Ok((matches, skipped))
+}
+
+/// Fails only when the command line or environment does not parse; `[server]` keys that do not
+/// apply are left out and reported.
+fn merge(
+    cmd: Command,
+    argv: Vec<OsString>,
+    table: &toml::Table,
+) -> Result<(ArgMatches, Sources, Vec<String>), clap::Error> {
+    let base = cmd.clone().try_get_matches_from(&argv)?;
+    let trusted = base.get_flag("trust_config_file");
+    let mut sources = Sources::new();
+    let mut skipped = Vec::new();
+    let mut extra: Vec<OsString> = Vec::new();
+    for (key, value) in table {
+        match file_args(&cmd, &argv, &base, trusted, key, value) {
+            Ok(Some((id, args))) => {
+                extra.extend(args);
+                sources.insert(id, Source::File);
+            }
+            Ok(None) => {}
+            Err(why) => skipped.push(format!("server.{key} is ignored: {why}")),
+        }
+    }
+
+    // each value parses on its own, but together they can still clash; then none of them apply
+    let matches = match cmd.clone().try_get_matches_from(argv.iter().cloned().chain(extra)) {
+        Ok(matches) => matches,
+        Err(e) => {
+            skipped.push(format!("[server] is ignored: {}", clap_message(&e)));
+            sources.clear();
+            base
+        }
+    };
+    for id in matches.ids() {
+        let source = match matches.value_source(id.as_str()) {
+            Some(ValueSource::CommandLine) if sources.contains_key(id.as_str()) => Source::File,
+            Some(ValueSource::CommandLine) => Source::Cli,
+            Some(ValueSource::EnvVariable) => Source::Env,
+            _ => Source::Default,
+        };
+        sources.insert(id.to_string(), source);
+    }
+    Ok((matches, sources, skipped))
+}
+
+/// The flags one `[server]` key stands for, by arg id; `None` when the command line or environment
+/// already sets it, and why the key is skipped when it cannot apply.
+fn file_args(
+    cmd: &Command,
+    argv: &[OsString],
+    base: &ArgMatches,
+    trusted: bool,
+    key: &str,
+    value: &toml::Value,
+) -> Result<Option<(String, Vec<OsString>)>, String> {
+    // keys are flag names, with either - or _ (`sink`, `view_token`, `history-limit`)
+    let name = key.replace('_', "-");
+    let arg = cmd
+        .get_arguments()
+        .find(|a| a.get_long() == Some(name.as_str()) || a.get_id() == key)
+        .ok_or("not a server setting")?;
+    let id = arg.get_id().as_str();
+    if NOT_LAYERED.contains(&id) || arg.is_positional() {
+        return Err("it cannot be set in [server]".into());
+    }
+    if !trusted && !FILE_SETTABLE.contains(&id) {
+        return Err(format!(
+            "set it on the command line or in {}{} (or pass --trust-config-file)",
+            ENV_PREFIX,
+            id.to_uppercase()
+        ));
+    }
+    if !matches!(base.value_source(id), None | Some(ValueSource::DefaultValue)) {
+        return Ok(None);
+    }
+    let values = match value {
+        toml::Value::Array(items) if matches_many(arg) => items.iter().map(scalar).collect::<Result<Vec<_>, _>>()?,
+        toml::Value::Array(_) => return Err("takes one value, not a list".into()),
+        v => vec![scalar(v)?],
+    };
+    let mut args = Vec::new();
+    for v in values {
+        args.push(format!("--{}", arg.get_long().unwrap_or(id)).into());
+        args.push(v.into());
+    }
+    // the command line parses on its own, so a failure here is this key's value
+    cmd.clone()
+        .try_get_matches_from(argv.iter().chain(&args))
+        .map_err(|e| clap_message(&e))?;
+    Ok(Some((id.to_string(), args)))
+}
+
+/// A clap error without the `error:` prefix and the usage hint, which mean nothing for a file.
+pub fn clap_message(e: &clap::Error) -> String {
+    let msg = e.to_string();
+    msg.lines()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches("error: ")
+        .to_string()
+}
+
+/// The `[server]` table, or an empty one and why when the file cannot give one.
+fn read_server_table(path: &Path) -> (toml::Table, Vec<String>) {
+    let ignored = |why: String| (toml::Table::new(), vec![format!("[server] is ignored: {why}")]);
+    let txt = match std::fs::read_to_string(path) {
+        Ok(txt) => txt,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (toml::Table::new(), Vec::new()),
+        Err(e) => return ignored(format!("read {:?}: {}", path, e)),
+    };
+    let mut doc: toml::Table = match toml::from_str(&txt) {
+        Ok(doc) => doc,
+        Err(e) => return ignored(format!("{} does not parse: {}", path.display(), e.message())),
+    };
+    match doc.remove("server") {
+        Some(toml::Value::Table(t)) => (t, Vec::new()),
+        Some(_) => ignored("it must be a table".into()),
+        None => (toml::Table::new(), Vec::new()),
+    }
+}
+
+/// A TOML value as it would be typed on the command line.
+fn scalar(value: &toml::Value) -> Result<String, String> {
+    Ok(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        other => return Err(format!("unsupported value {}", other)),
+    })
+}
+
+/// `--print-config`: the effective server settings as a `[server]` table, each with its source.
+pub fn print_config(cmd: &Command, matches: &ArgMatches, sources: &Sources) {
+    println!("[server]");
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        if NOT_LAYERED.contains(&id) || arg.is_positional() {
+            continue;
+        }
+        let raw: Vec<String> = matches
+            .get_raw(id)
+            .map(|vals| vals.map(|v| v.to_string_lossy().into_owned()).collect())
+            .unwrap_or_default();
+        let env = format!("{}{}", ENV_PREFIX, id.to_uppercase());
+        let value = match (matches_many(arg), raw.as_slice()) {
+            (_, [_, ..]) if id.contains("token") => "\"<redacted>\"".to_string(),
+            (false, []) => {
+                println!("# {id} is unset ({env})");
+                continue;
+            }
+            (false, [one]) => toml_scalar(one),
+            (_, vals) => format!(
+                "[{}]",
+                vals.iter().map(|v| toml_scalar(v)).collect::<Vec<_>>().join(", ")
+            ),
+        };
+        let source = sources.get(id).copied().unwrap_or(Source::Default);
+        println!("{id} = {value}  # {} ({env})", source.name());
+    }
+}
+
+fn matches_many(arg: &clap::Arg) -> bool {
+    matches!(arg.get_action(), clap::ArgAction::Append)
+}
+
+fn toml_scalar(raw: &str) -> String {
+    match raw
+        .parse::<i64>()
+        .ok()
+        .map(|i| i.to_string())
+        .or_else(|| raw.parse::<f64>().ok().map(|f| f.to_string()))
+    {
+        Some(n) if n == raw => n,
+        _ => toml::Value::String(raw.to_string()).to_string(),
+    }
+}
+
+/// JSON Schema of the `[server]` table: every layered flag, keyed by id, with its help and default.
+pub fn schema(cmd: &Command) -> serde_json::Value {
+    let mut props = serde_json::Map::new();
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        if NOT_LAYERED.contains(&id) || arg.is_positional() {
+            continue;
+        }
+        let choices: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|v| !v.is_hide_set())
+            .map(|v| v.get_name().to_string())
+            .collect();
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect();
+        // clap lists true/false as the choices of a bool flag
+        let mut value = match json_type(arg) {
+            ty if ty == "boolean" || choices.is_empty() => json!({ "type": ty }),
+            _ => json!({ "enum": choices }),
+        };
+        if matches_many(arg) {
+            value = json!({ "anyOf": [value.clone(), { "type": "array", "items": value }] });
+        }
+        let mut help = arg
+            .get_long_help()
+            .or(arg.get_help())
+            .map(|h| h.to_string())
+            .unwrap_or_default();
+        if !FILE_SETTABLE.contains(&id) {
+            help.push_str("\nOnly read from this file with --trust-config-file");
+        }
+        value["description"] = json!(help.trim_start());
+        if let [one] = defaults.as_slice() {
+            value["default"] = serde_json::from_str(&toml_scalar(one)).unwrap_or_else(|_| json!(one));
+        }
+        props.insert(id.to_string(), value);
+    }
+    json!({
+        "description": "Server flags, by name; see --help. The command line and XTERM_RS_* variables override them.",
+        "type": "object",
+        "properties": props,
+    })
+}
+
+fn json_type(arg: &clap::Arg) -> &'static str {
+    let ty = arg.get_value_parser().type_id();
+    let any_of = |ids: &[TypeId]| ids.iter().any(|id| ty == *id);
+    if any_of(&[TypeId::of::<bool>()]) {
+        "boolean"
+    } else if any_of(&[
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<usize>(),
+    ]) {
+        "integer"
+    } else if any_of(&[TypeId::of::<f32>(), TypeId::of::<f64>()]) {
+        "number"
+    } else {
+        "string"
+    }
+}
@@ -0,0 +1,194 @@
+use crate::models::layout::{builtin_layouts, find_layout, is_known_code};
+use crate::models::{AppConfig, is_hex_color};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // the file was rejected and the last good config stays active
+    Error,
+    Warning,
+}
+
+/// A problem found in config.toml, pushed to clients as part of a `config-error` event.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub message: String,
+    // 1-based, when the problem can be pinned to a place in the file
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ConfigIssue {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            line: None,
+            column: None,
+        }
+    }
+
+    fn at(mut self, pos: Option<(usize, usize)>) -> Self {
+        if let Some((line, column)) = pos {
+            self.line = Some(line);
+            self.column = Some(column);
+        }
+        self
+    }
+}
+
+/// Parses and checks config.toml. The config is `None` when any issue is an error;
+/// unknown keys are only warnings.
+pub fn parse_cfg(txt: &str) -> (Option<AppConfig>, Vec<ConfigIssue>) {
+    let mut unknown = Vec::new();
+    let de = toml::Deserializer::new(txt);
+    let cfg: AppConfig = match serde_ignored::deserialize(de, |path| unknown.push(path.to_string())) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            let pos = e.span().map(|span| line_col(txt, span.start));
+            return (None, vec![ConfigIssue::error(e.message()).at(pos)]);
+        }
+    };
+
+    let mut issues: Vec<ConfigIssue> = unknown
+        .iter()
+        .map(|path| {
+            let issue = ConfigIssue {
+                severity: Severity::Warning,
+                message: format!("unknown key '{path}' is ignored"),
+                line: None,
+                column: None,
+            };
+            issue.at(find_key(txt, path))
+        })
+        .collect();
+
+    for (path, message) in check(&cfg) {
+        issues.push(ConfigIssue::error(format!("{path}: {message}")).at(find_key(txt, &path)));
+    }
+
+    match issues.iter().any(|i| i.severity == Severity::Error) {
+        true => (None, issues),
+        false => (Some(cfg), issues),
+    }
+}
+
+/// Value checks the types alone cannot express.
+fn check(cfg: &AppConfig) -> Vec<(String, String)> {
+    let mut errs = Vec::new();
+    if !(1.0..=200.0).contains(&cfg.font.size) {
+        errs.push(("font.size".into(), format!("{} is outside 1..=200", cfg.font.size)));
+    }
+    if !(0.5..=4.0).contains(&cfg.font.line_height) {
+        errs.push((
+            "font.line_height".into(),
+            format!("{} is outside 0.5..=4", cfg.font.line_height),
+        ));
+    }
+    if cfg.scrollback > 1_000_000 {
+        errs.push((
+            "scrollback".into(),
+            format!("{} is more than 1000000 lines", cfg.scrollback),
+        ));
+    }
+    if cfg.font.family.trim().is_empty() {
+        errs.push(("font.family".into(), "must not be empty".into()));
+    }
+    if find_layout(&cfg.layout, &cfg.layouts).is_none() {
+        let known: Vec<String> = builtin_layouts()
+            .map(String::from)
+            .chain(cfg.layouts.keys().cloned())
+            .collect();
+        errs.push((
+            "layout".into(),
+            format!("unknown layout '{}', expected one of {}", cfg.layout, known.join(", ")),
+        ));
+    }
+    for (name, keys) in &cfg.layouts {
+        for (code, pair) in keys {
+            if !is_known_code(code) {
+                errs.push((
+                    format!("layouts.{name}.{code}"),
+                    "not a remappable key code (like KeyQ or Digit1)".into(),
+                ));
+            }
+            if [&pair.0, &pair.1]
+                .iter()
+                .any(|s| s.is_empty() || s.chars().any(char::is_control))
+            {
+                errs.push((format!("layouts.{name}.{code}"), "keys must type printable text".into()));
+            }
+        }
+    }
+    if cfg.theme.names().iter().any(|name| name.trim().is_empty()) {
+        errs.push(("theme".into(), "names must not be empty".into()));
+    }
+    for (name, profile) in &cfg.profiles {
+        let at = |key: &str| format!("profiles.{name}.{key}");
+        // names go in urls as ?profile=
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            errs.push((
+                format!("profiles.{name}"),
+                "names may only use letters, digits, - and _".into(),
+            ));
+        }
+        if profile.command.as_deref().is_some_and(|c| c.trim().is_empty()) {
+            errs.push((at("command"), "must not be empty".into()));
+        }
+        if profile.term.as_deref().is_some_and(|t| t.trim().is_empty()) {
+            errs.push((at("term"), "must not be empty".into()));
+        }
+        for (key, size) in [("rows", profile.rows), ("cols", profile.cols)] {
+            if size.is_some_and(|n| !(1..=1000).contains(&n)) {
+                errs.push((at(key), format!("{} is outside 1..=1000", size.unwrap_or_default())));
+            }
+        }
+        for var in profile.env.keys() {
+            if var.is_empty() || var.contains(['=', '\0']) {
+                errs.push((at("env"), format!("'{var}' is not a valid variable name")));
+            }
+        }
+        if let Some(theme) = &profile.theme
+            && theme.names().iter().any(|name| name.trim().is_empty())
+        {
+            errs.push((at("theme"), "names must not be empty".into()));
+        }
+    }
+    for (name, theme) in &cfg.themes {
+        for (key, color) in theme.colors() {
+            if !is_hex_color(color) {
+                errs.push((
+                    format!("themes.{name}.{key}"),
+                    format!("'{color}' is not a #rrggbb color"),
+                ));
+            }
+        }
+    }
+    errs
+}
+
+fn line_col(txt: &str, offset: usize) -> (usize, usize) {
+    let before = &txt[..offset.min(txt.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map_or(before.len(), |nl| before.len() - nl - 1) + 1;
+    (line, column)
+}
+
+/// Best-effort position of a dotted key: the last segment assigned on a line, or as a table header,
+/// looked for under the key's own `[table]` header when the file has one.
+fn find_key(txt: &str, path: &str) -> Option<(usize, usize)> {
+    let (parent, key) = path.rsplit_once('.').unwrap_or(("", path));
+    let header = format!("[{parent}]");
+    let skip = txt.lines().position(|line| line.trim() == header).unwrap_or(0);
+    txt.lines().enumerate().skip(skip).find_map(|(i, line)| {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let rest = trimmed.strip_prefix('[').unwrap_or(trimmed).trim_start_matches('[');
+        let matched = rest
+            .strip_prefix(key)
+            .is_some_and(|after| after.trim_start().starts_with(['=', ']', '.']));
+        matched.then_some((i + 1, indent + 1))
+    })
+}
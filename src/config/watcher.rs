@@ -1,29 +1,59 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use notify_debouncer_mini::{
-    DebouncedEventKind::{Any, AnyContinuous},
-    new_debouncer,
-    notify::RecursiveMode,
+    Config, DebounceEventResult, Debouncer, new_debouncer, new_debouncer_opt,
+    notify::{self, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher},
 };
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
-use tokio::sync::{mpsc, watch};
+use tokio::sync::{Mutex, mpsc, watch};
 use tokio::task::JoinHandle;
 
-use crate::models::AppConfig;
+use super::edit::merge_update;
+use super::validate::{ConfigIssue, Severity, parse_cfg};
+use crate::models::{AppConfig, logger};
 
-async fn read_cfg(path: &Path) -> Result<AppConfig> {
-    let txt = tokio::fs::read_to_string(path)
-        .await
-        .with_context(|| format!("read {:?}", path))?;
-    Ok(toml::from_str(&txt)?)
+const DEBOUNCE: Duration = Duration::from_millis(50);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn log_issues(path: &Path, issues: &[ConfigIssue]) {
+    for issue in issues {
+        let kind = match issue.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warn",
+        };
+        let at = match (issue.line, issue.column) {
+            (Some(line), Some(col)) => format!(":{line}:{col}"),
+            _ => String::new(),
+        };
+        logger(kind, format!("{}{}: {}", path.display(), at, issue.message));
+    }
+}
+
+/// Problems with the config, reported to clients together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Issues {
+    // the file on disk; empty once it loads cleanly
+    pub file: Vec<ConfigIssue>,
+    // [server] settings that could not be applied live
+    pub server: Vec<ConfigIssue>,
+}
+
+impl Issues {
+    pub fn all(&self) -> Vec<ConfigIssue> {
+        self.file.iter().chain(&self.server).cloned().collect()
+    }
 }
 
 #[derive(Clone)]
 pub struct ConfigWatcher {
     inner: Arc<watch::Sender<AppConfig>>,
+    issues: Arc<watch::Sender<Issues>>,
+    path: PathBuf,
+    // one update at a time, so concurrent edits do not overwrite each other
+    writing: Arc<Mutex<()>>,
 }
 
 impl ConfigWatcher {
@@ -33,49 +63,236 @@ impl ConfigWatcher {
     pub fn subscribe(&self) -> watch::Receiver<AppConfig> {
         self.inner.subscribe()
     }
+    pub fn issues(&self) -> watch::Receiver<Issues> {
+        self.issues.subscribe()
+    }
+    pub fn set_server_issues(&self, issues: Vec<ConfigIssue>) {
+        self.issues
+            .send_if_modified(|cur| std::mem::replace(&mut cur.server, issues.clone()) != issues);
+    }
+
+    /// Writes a partial update into config.toml; the watch then picks it up like a hand edit.
+    pub async fn update(&self, update: &serde_json::Value) -> Result<(), Vec<ConfigIssue>> {
+        let _writing = self.writing.lock().await;
+        // a symlinked config is edited where it really lives
+        let path = tokio::fs::canonicalize(&self.path)
+            .await
+            .unwrap_or_else(|_| self.path.clone());
+        let txt = match tokio::fs::read_to_string(&path).await {
+            Ok(txt) => txt,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(vec![ConfigIssue::error(format!("read {:?}: {}", path, e))]),
+        };
+        let out = merge_update(&txt, update)?;
+        if out != txt {
+            write_replace(&path, &out)
+                .await
+                .map_err(|e| vec![ConfigIssue::error(format!("write {:?}: {}", path, e))])?;
+        }
+        Ok(())
+    }
 }
 
-pub async fn spawn_cfg_watcher(path: PathBuf) -> Result<(ConfigWatcher, JoinHandle<()>)> {
-    let init_cfg = read_cfg(&path).await.unwrap_or_else(|_| AppConfig {
-        layout: "qwerty".into(),
-        theme: "Default".into(),
-    });
+/// Replaces the file in one step, so the watch never reads it half written.
+async fn write_replace(path: &Path, txt: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, txt).await?;
+    // the new file would otherwise get default permissions, e.g. lose a 0600 the user set
+    match tokio::fs::metadata(path).await {
+        Ok(meta) => tokio::fs::set_permissions(&tmp, meta.permissions()).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    tokio::fs::rename(&tmp, path).await
+}
 
-    let (tx, _rx_cfg) = watch::channel(init_cfg.clone());
+/// Modification time and size, enough to tell that the file changed without reading it.
+async fn stamp(path: &Path) -> Option<(std::time::SystemTime, u64)> {
+    let meta = tokio::fs::metadata(path).await.ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
 
-    let tx_in_task = tx.clone();
-    let path_in_task = path.clone();
-    let dir_in_task = path.parent().unwrap().to_path_buf();
-    let target_name = path.file_name().unwrap().to_owned();
+/// Loads the file when its contents changed since the last call.
+struct Loader {
+    path: PathBuf,
+    last: Option<String>,
+    cfg: watch::Sender<AppConfig>,
+    issues: watch::Sender<Issues>,
+}
 
-    let (tx_async, mut rx_async) = mpsc::channel(8);
-    let mut debouncer = new_debouncer(Duration::from_millis(50), move |res| {
-        if let Ok(events) = res {
-            let _ = tx_async.blocking_send(events);
+impl Loader {
+    async fn reload(&mut self) {
+        // a missing file keeps whatever was loaded; it may just be mid-swap
+        let txt = match tokio::fs::read_to_string(&self.path).await {
+            Ok(txt) => txt,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                let issues = vec![ConfigIssue::error(format!("read {:?}: {}", self.path, e))];
+                log_issues(&self.path, &issues);
+                self.issues.send_modify(|cur| cur.file = issues);
+                return;
+            }
+        };
+        if self.last.as_ref() == Some(&txt) {
+            return;
+        }
+        // a bad edit keeps the last good config active
+        let (cfg, issues) = parse_cfg(&txt);
+        self.last = Some(txt);
+        log_issues(&self.path, &issues);
+        if let Some(cfg) = cfg {
+            self.cfg.send_replace(cfg);
         }
-    })?;
-    debouncer.watcher().watch(&dir_in_task, RecursiveMode::NonRecursive)?;
+        self.issues
+            .send_if_modified(|cur| std::mem::replace(&mut cur.file, issues.clone()) != issues);
+    }
+}
 
-    let handle = tokio::spawn(async move {
-        while let Some(events) = rx_async.recv().await {
-            for ev in events {
-                if ev.path.file_name() != Some(&target_name) {
-                    continue;
-                }
-                if !matches!(ev.kind, Any | AnyContinuous) {
-                    continue;
+/// Directories whose changes can alter what the config path reads as.
+fn watch_targets(path: &Path) -> Vec<PathBuf> {
+    let parent = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut dirs = Vec::new();
+    // the config directory, or its nearest existing ancestor until it is created
+    if let Some(dir) = parent.ancestors().find(|d| d.is_dir()) {
+        dirs.push(dir.to_path_buf());
+    }
+    // a symlinked file (e.g. a ConfigMap's ..data/config.toml) changes where it really lives too
+    if let Ok(real) = std::fs::canonicalize(path)
+        && let Some(dir) = real.parent()
+        && !dirs.iter().any(|d| d == dir)
+    {
+        dirs.push(dir.to_path_buf());
+    }
+    dirs
+}
+
+enum Backend {
+    Native(Debouncer<RecommendedWatcher>),
+    // for filesystems or sandboxes without inotify
+    Poll(Debouncer<PollWatcher>),
+}
+
+impl Backend {
+    fn poll(tx: mpsc::Sender<DebounceEventResult>) -> Result<Self> {
+        let config = Config::default()
+            .with_timeout(DEBOUNCE)
+            .with_notify_config(notify::Config::default().with_poll_interval(POLL_INTERVAL));
+        let debouncer = new_debouncer_opt::<_, PollWatcher>(config, move |res| {
+            let _ = tx.blocking_send(res);
+        })?;
+        Ok(Backend::Poll(debouncer))
+    }
+
+    fn watcher(&mut self) -> &mut dyn Watcher {
+        match self {
+            Backend::Native(d) => d.watcher(),
+            Backend::Poll(d) => d.watcher(),
+        }
+    }
+}
+
+/// Keeps watches on `watch_targets`, re-arming after every change since directories
+/// appear and symlinks move. Falls back to polling when native watches fail.
+struct Watches {
+    backend: Backend,
+    dirs: Vec<PathBuf>,
+    tx: mpsc::Sender<DebounceEventResult>,
+}
+
+impl Watches {
+    fn new(tx: mpsc::Sender<DebounceEventResult>) -> Result<Self> {
+        let events = tx.clone();
+        let backend = match new_debouncer(DEBOUNCE, move |res| {
+            let _ = events.blocking_send(res);
+        }) {
+            Ok(debouncer) => Backend::Native(debouncer),
+            Err(e) => {
+                logger("warn", format!("Config watcher unavailable, polling instead: {}", e));
+                Backend::poll(tx.clone())?
+            }
+        };
+        Ok(Self {
+            backend,
+            dirs: Vec::new(),
+            tx,
+        })
+    }
+
+    fn disarm(&mut self) {
+        for dir in self.dirs.drain(..) {
+            let _ = self.backend.watcher().unwatch(&dir);
+        }
+    }
+
+    fn arm(&mut self, path: &Path) {
+        for dir in watch_targets(path) {
+            if let Err(e) = self.backend.watcher().watch(&dir, RecursiveMode::NonRecursive) {
+                if matches!(self.backend, Backend::Native(_)) {
+                    logger("warn", format!("Cannot watch {:?}, polling instead: {}", dir, e));
+                    match Backend::poll(self.tx.clone()) {
+                        Ok(poll) => {
+                            self.disarm();
+                            self.backend = poll;
+                            return self.arm(path);
+                        }
+                        Err(e) => logger("error", format!("Config polling failed: {}", e)),
+                    }
                 }
+                continue;
+            }
+            self.dirs.push(dir);
+        }
+    }
+}
 
-                // pause
-                let _ = debouncer.watcher().unwatch(&dir_in_task);
-                if let Ok(cfg) = read_cfg(&path_in_task).await {
-                    let _ = tx_in_task.send(cfg);
+pub async fn spawn_cfg_watcher(path: PathBuf) -> Result<(ConfigWatcher, JoinHandle<()>)> {
+    let (tx, _rx_cfg) = watch::channel(AppConfig::default());
+    let (issues_tx, _) = watch::channel(Issues::default());
+
+    let mut loader = Loader {
+        path: path.clone(),
+        last: None,
+        cfg: tx.clone(),
+        issues: issues_tx.clone(),
+    };
+    loader.reload().await;
+
+    let (tx_async, mut rx_async) = mpsc::channel(8);
+    let mut watches = Watches::new(tx_async)?;
+    watches.arm(&path);
+
+    let cfg_path = path.clone();
+    let handle = tokio::spawn(async move {
+        while let Some(res) = rx_async.recv().await {
+            if res.is_ok_and(|events| events.is_empty()) {
+                continue;
+            }
+            loop {
+                // pause while reading, so our own access does not come back as an event
+                watches.disarm();
+                let seen = stamp(&path).await;
+                loader.reload().await;
+                watches.arm(&path);
+                // a swap while disarmed sent no event; a stat, unlike a read, does not send one either
+                if stamp(&path).await == seen {
+                    break;
                 }
-                // resume
-                let _ = debouncer.watcher().watch(&dir_in_task, RecursiveMode::NonRecursive);
             }
         }
     });
 
-    Ok((ConfigWatcher { inner: Arc::new(tx) }, handle))
+    let watcher = ConfigWatcher {
+        inner: Arc::new(tx),
+        issues: Arc::new(issues_tx),
+        path: cfg_path,
+        writing: Arc::default(),
+    };
+    Ok((watcher, handle))
 }
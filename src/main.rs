@@ -2,11 +2,16 @@
 // dir  := .
 // kid  :=
 use anyhow::Context;
-use axum::{Extension, Router, routing::get};
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use axum::{
+    Extension, Router,
+    routing::{get, patch},
+};
+use std::net::SocketAddr;
+use std::sync::{Arc, atomic::AtomicU64};
+use std::time::Duration;
 use tower_http::services::ServeDir;
 
+mod api;
 mod caster;
 mod config;
 mod index;
@@ -16,22 +21,32 @@ mod sockets;
 
 use index::index;
 
-use caster::Caster;
+use caster::sink::SinkSpec;
 use config::spawn_cfg_watcher;
-use models::{AppState, logger};
-use pty::PtyManager;
-use sockets::{ws_handler, ws_handler_debug};
+use models::{AppState, AuthOpts, KeepaliveOpts, OutputOpts, logger};
+use pty::SizePolicy;
+use pty::terminal::{DEFAULT_PROFILE, TerminalOpts, Terminals};
+use sockets::{ws_handler, ws_handler_debug, ws_handler_view};
 
-use clap::{Parser, ValueHint};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = "TODO")]
+#[command(
+    author,
+    version,
+    about,
+    long_about = "Serve a shared terminal over websockets.\n\nServer flags can also be set in the [server] table of the config file\nor as XTERM_RS_<FLAG> environment variables; precedence is\ndefaults < file < env < command line.",
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    action: Option<Action>,
+
     #[arg(
         short,
         long,
         default_value = "/bin/bash",
-        long_help = "Command to run in the terminal"
+        long_help = "Command to run in the terminal\nUsed by shell profiles without a command of their own"
     )]
     command: String,
 
@@ -41,8 +56,9 @@ struct Args {
     #[arg(long, default_value_t = 80u16, long_help = "Terminal initial columns")]
     cols: u16,
 
-    #[arg(long, value_hint=ValueHint::DirPath, long_help = "Path to static files")]
-    resource: std::path::PathBuf,
+    // required to serve, but checked there so --print-config and [server] can do without it
+    #[arg(long, value_hint=ValueHint::DirPath, long_help = "Path to static files (required to serve)")]
+    resource: Option<std::path::PathBuf>,
 
     #[arg(
         long,
@@ -80,48 +96,314 @@ struct Args {
         long,
         default_value_t = 120u32,
         value_parser = clap::value_parser!(u32).range(10..=3600),
-        long_help = "Verbose log interval (s)\nOnly used when log_level is 2"
+        long_help = "Verbose log interval (s)\nHow often stdout and remote sinks ship their batch"
     )]
     verbose_interval: u32,
+
+    #[arg(
+        long,
+        default_value_t = 60u32,
+        value_parser = clap::value_parser!(u32).range(1..=1000),
+        long_help = "Maximum output frames per second sent to each client"
+    )]
+    max_fps: u32,
+
+    #[arg(
+        long,
+        default_value_t = 65536usize, // 64KB
+        long_help = "Maximum websocket frame size for output (bytes)"
+    )]
+    max_frame_size: usize,
+
+    #[arg(
+        long,
+        long_help = "When a client has more than this many output bytes queued,\nskip to the latest screen instead of replaying everything"
+    )]
+    skip_behind: Option<usize>,
+
+    #[arg(
+        long,
+        default_value_t = 15u32,
+        value_parser = clap::value_parser!(u32).range(1..=600),
+        long_help = "How often the server pings each client (s)"
+    )]
+    ping_interval: u32,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SizePolicy::Latest,
+        long_help = "How the terminal size is chosen when several clients are attached"
+    )]
+    size_policy: SizePolicy,
+
+    #[arg(
+        long,
+        long_help = "Require this token (?token=...) for clients that may type into the terminal"
+    )]
+    token: Option<String>,
+
+    #[arg(
+        long = "view-token",
+        long_help = "Token granting read-only access, repeatable\n/view is always read-only"
+    )]
+    view_tokens: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = 45u32,
+        value_parser = clap::value_parser!(u32).range(5..=3600),
+        long_help = "Close a client that has sent nothing, not even a pong, for this long (s)"
+    )]
+    client_timeout: u32,
+
+    #[arg(
+        long = "sink",
+        long_help = "Extra recording sink, repeatable:\n  stdout\n  unix:<socket path>\n  http(s)://<collector url>"
+    )]
+    sinks: Vec<SinkSpec>,
+
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        long_help = "Where undelivered chunks for remote sinks wait for retry (default: <log_dir>/spool)"
+    )]
+    spool_dir: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        long_help = "Let the [server] table of the config file set every flag, including recording,\naccess tokens and the command; only for config files students cannot edit"
+    )]
+    trust_config_file: bool,
+
+    #[arg(
+        long,
+        long_help = "Print the effective server settings and where each came from, then exit"
+    )]
+    print_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Summarize recorded sessions (active time, typing rate, idle periods, commands)
+    Report {
+        #[arg(
+            long,
+            value_hint = ValueHint::DirPath,
+            default_value = "/home/student/.local/state/workspace-logs/",
+        )]
+        log_dir: std::path::PathBuf,
+
+        #[arg(long, value_enum, default_value = "json")]
+        format: caster::report::ReportFormat,
+
+        #[arg(
+            long,
+            default_value_t = 300.0,
+            long_help = "Gap between keystrokes (s) after which the student counts as idle"
+        )]
+        idle: f64,
+
+        #[arg(value_hint = ValueHint::FilePath, long_help = "Cast files to report on (default: all in log_dir)")]
+        files: Vec<std::path::PathBuf>,
+    },
+
+    /// Print the JSON Schema of config.toml, for editors that complete and check TOML against one
+    Schema {
+        #[arg(
+            long,
+            value_hint = ValueHint::DirPath,
+            long_help = "Path to static files, for the built-in theme names"
+        )]
+        resource: Option<std::path::PathBuf>,
+    },
+
+    /// Tools for cast recordings
+    Cast {
+        #[command(subcommand)]
+        action: CastAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CastAction {
+    /// Rebuild cast files from the `["cast", ...]` lines printed at log_level 2
+    Decode {
+        #[arg(value_hint = ValueHint::FilePath, long_help = "Server log to read (default: stdin)")]
+        input: Option<std::path::PathBuf>,
+
+        #[arg(short, long, value_hint = ValueHint::DirPath, default_value = ".")]
+        out_dir: std::path::PathBuf,
+    },
+
+    /// Render a recording to an animated SVG or a plain-text transcript of the screen at each marker
+    Render {
+        #[arg(value_hint = ValueHint::FilePath)]
+        input: std::path::PathBuf,
+
+        #[arg(long, value_enum, default_value = "svg")]
+        format: caster::render::RenderFormat,
+
+        #[arg(short, long, value_hint = ValueHint::FilePath, long_help = "Output file (default: stdout)")]
+        out: Option<std::path::PathBuf>,
+
+        #[arg(long, long_help = "Theme name (default: the one in config_path)")]
+        theme: Option<String>,
+
+        #[arg(
+            long,
+            value_hint = ValueHint::FilePath,
+            default_value = "/home/student/.config/config.toml"
+        )]
+        config_path: std::path::PathBuf,
+
+        #[arg(long, value_hint = ValueHint::DirPath, long_help = "Path to static files, for theme lookup")]
+        resource: Option<std::path::PathBuf>,
+
+        #[arg(long, default_value_t = 24u16, long_help = "Terminal rows before the first resize")]
+        rows: u16,
+
+        #[arg(
+            long,
+            default_value_t = 80u16,
+            long_help = "Terminal columns before the first resize"
+        )]
+        cols: u16,
+
+        #[arg(long, default_value_t = 15.0, long_help = "Maximum SVG frames per second")]
+        fps: f32,
+
+        #[arg(
+            long,
+            default_value_t = 2.0,
+            long_help = "Longest pause kept in the SVG animation (s)"
+        )]
+        max_idle: f32,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    let pty = Arc::new(PtyManager::new(args.rows, args.cols, args.history_limit).await?);
-
-    let ts_millis = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("time went backwards")
-        .as_millis();
-    let start = std::time::Instant::now();
-
-    let caster = match args.log_level {
-        0 => None,
-        x => Some(Caster::new(
-            args.log_dir,
-            start,
-            ts_millis,
-            x == 2,
-            args.verbose_interval,
-            (args.rows, args.cols),
-        )?),
-    };
+    let cmd = config::server::with_env(Args::command());
+    let (matches, sources, skipped) = config::server::layered_matches(cmd.clone())?;
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    for msg in &skipped {
+        logger("warn", msg);
+    }
+    if args.print_config {
+        config::server::print_config(&cmd, &matches, &sources);
+        return Ok(());
+    }
+    let layers = (cmd, matches);
+    match args.action {
+        Some(Action::Report {
+            log_dir,
+            format,
+            idle,
+            files,
+        }) => caster::report::run(&log_dir, files, format, idle),
+        Some(Action::Schema { resource }) => {
+            let themes = config::schema::builtin_theme_names(resource.as_deref())?;
+            println!("{:#}", config::schema::config_schema(&layers.0, &themes));
+            Ok(())
+        }
+        Some(Action::Cast {
+            action: CastAction::Decode { input, out_dir },
+        }) => caster::stream::run(input, &out_dir),
+        Some(Action::Cast {
+            action:
+                CastAction::Render {
+                    input,
+                    format,
+                    out,
+                    theme,
+                    config_path,
+                    resource,
+                    rows,
+                    cols,
+                    fps,
+                    max_idle,
+                },
+        }) => {
+            let theme = match format {
+                caster::render::RenderFormat::Svg => {
+                    caster::render::resolve_theme(theme, &config_path, resource.as_deref())?
+                }
+                caster::render::RenderFormat::Text => models::Theme::default(),
+            };
+            let opts = caster::render::RenderOpts {
+                format,
+                theme,
+                fps,
+                max_idle,
+                size: (rows, cols),
+            };
+            caster::render::run(&input, out.as_deref(), opts)
+        }
+        None => serve(args, layers).await,
+    }
+}
 
+/// `layers` is the parsed command line, re-applied as `[server]` in config.toml changes.
+async fn serve(args: Args, layers: (clap::Command, clap::ArgMatches)) -> anyhow::Result<()> {
+    let resource = args.resource.context("--resource (or XTERM_RS_RESOURCE) is required")?;
     let (cfg_watcher, _join) = spawn_cfg_watcher(args.config_path).await?;
+    let themes = config::schema::builtin_theme_names(Some(&resource)).unwrap_or_else(|e| {
+        logger("warn", format!("config schema lists no built-in themes: {e:#}"));
+        vec!["Default".into()]
+    });
+
+    let terminals = Terminals::new(
+        TerminalOpts {
+            command: args.command,
+            rows: args.rows,
+            cols: args.cols,
+            size_policy: args.size_policy,
+            spool_dir: args.spool_dir.clone().unwrap_or_else(|| args.log_dir.join("spool")),
+            log_dir: args.log_dir,
+            sinks: args.sinks,
+            history_limit: args.history_limit,
+            log_level: args.log_level,
+            verbose_interval: args.verbose_interval,
+        },
+        cfg_watcher.clone(),
+    );
+    // the default terminal runs (and records) from the start; other profiles when first opened
+    terminals.open(DEFAULT_PROFILE).await?;
 
     let state = Arc::new(AppState {
-        start,
-        pty: Arc::clone(&pty),
-        caster,
+        start: std::time::Instant::now(),
+        next_client: AtomicU64::new(1),
+        terminals,
         watcher: cfg_watcher,
-        stty_size: Arc::new(tokio::sync::RwLock::new((args.rows, args.cols))),
+        output: OutputOpts {
+            max_fps: args.max_fps,
+            max_frame: args.max_frame_size.max(1),
+            skip_behind: args.skip_behind,
+        },
+        keepalive: tokio::sync::watch::Sender::new(KeepaliveOpts {
+            interval: Duration::from_secs(args.ping_interval.into()),
+            timeout: Duration::from_secs(args.client_timeout.into()),
+        }),
+        auth: std::sync::RwLock::new(AuthOpts {
+            token: args.token,
+            view_tokens: args.view_tokens,
+        }),
+        schema: config::schema::config_schema(&layers.0, &themes),
     });
+    let (cmd, matches) = layers;
+    config::live::spawn_server_reload(Arc::clone(&state), cmd, matches);
+    pty::terminal::spawn_profile_sync(Arc::clone(&state));
 
     let app = Router::new()
-        .nest_service("/static", ServeDir::new(args.resource))
+        .nest_service("/static", ServeDir::new(resource))
         .route("/ws", get(ws_handler))
         .route("/", get(index))
+        .route("/view", get(index))
+        .route("/view/ws", get(ws_handler_view))
+        .route("/api/clients", get(api::list_clients))
+        .route("/api/config", patch(api::update_config))
+        .route("/api/config/schema", get(api::config_schema))
         .route("/debug", get(index))
         .route("/debug/ws", get(ws_handler_debug))
         .layer(Extension(state));
@@ -130,5 +412,7 @@ async fn main() -> anyhow::Result<()> {
 
     logger("info", format!("Listening on http://{}", listener.local_addr()?));
 
-    axum::serve(listener, app).await.context("server error")
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .context("server error")
 }
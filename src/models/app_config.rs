@@ -0,0 +1,182 @@
+use super::Theme;
+use super::layout::{KeyMap, find_layout};
+use crate::config::schema::{LayoutName, ServerTable, ThemeName};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Settings from config.toml, hot-reloaded and pushed to clients in the `config` event.
+/// Every key is optional; missing keys keep their defaults.
+// config::schema derives the JSON Schema from these types; field doc comments are what editors show
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct AppConfig {
+    /// A built-in layout or one from `layouts`
+    #[schemars(with = "LayoutName")]
+    pub layout: String,
+    /// Layouts defined in the file, by name; they shadow built-in layouts of the same name
+    pub layouts: BTreeMap<String, KeyMap>,
+    pub theme: ThemeChoice,
+    /// Palettes defined in the file, by name; they shadow built-in themes of the same name
+    pub themes: BTreeMap<String, Theme>,
+    /// Terminals a client can open with `?profile=NAME`; `default` replaces --command
+    #[schemars(extend("propertyNames" = { "pattern": "^[A-Za-z0-9_-]+$" }))]
+    pub profiles: BTreeMap<String, ShellProfile>,
+    pub font: FontConfig,
+    pub cursor: CursorConfig,
+    /// Lines kept above the screen in the browser
+    #[schemars(range(max = 1_000_000))]
+    pub scrollback: u32,
+    pub bell: BellStyle,
+    pub right_click: RightClick,
+    /// Characters that end a word for double-click selection
+    pub word_separators: String,
+    // server flags, read by config::server and never sent to clients
+    #[serde(skip_serializing)]
+    #[schemars(with = "ServerTable")]
+    pub server: toml::Table,
+}
+
+impl AppConfig {
+    /// The layout in use; an unknown name (already reported) types as qwerty.
+    pub fn keymap(&self) -> KeyMap {
+        find_layout(&self.layout, &self.layouts).unwrap_or_default()
+    }
+
+    /// The config as clients of a profile's terminal see it.
+    pub fn for_profile(mut self, profile: &str) -> Self {
+        if let Some(theme) = self.profiles.get(profile).and_then(|p| p.theme.clone()) {
+            self.theme = theme;
+        }
+        self
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            layout: "qwerty".into(),
+            layouts: BTreeMap::new(),
+            theme: ThemeChoice::Named("Default".into()),
+            themes: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            font: FontConfig::default(),
+            cursor: CursorConfig::default(),
+            scrollback: 1000,
+            bell: BellStyle::None,
+            right_click: RightClick::Menu,
+            word_separators: " ()[]{}',\"`".into(),
+            server: toml::Table::new(),
+        }
+    }
+}
+
+/// A kind of terminal, started when its first client connects and shared by everyone on it.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct ShellProfile {
+    /// Program to run; defaults to --command
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    /// Working directory; a leading `~/` is the server user's home
+    pub cwd: Option<String>,
+    /// Variables set on top of the server's own environment
+    pub env: BTreeMap<String, String>,
+    /// TERM for the shell, xterm-color if unset
+    pub term: Option<String>,
+    /// Rows until a client resizes the terminal
+    pub rows: Option<u16>,
+    /// Columns until a client resizes the terminal
+    pub cols: Option<u16>,
+    /// Theme for this profile's clients instead of the top-level one
+    pub theme: Option<ThemeChoice>,
+}
+
+/// `theme = "Name"`, or `theme = { light = "A", dark = "B" }` to follow the browser's color scheme.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ThemeChoice {
+    Named(#[schemars(with = "ThemeName")] String),
+    Auto {
+        #[schemars(with = "ThemeName")]
+        light: String,
+        #[schemars(with = "ThemeName")]
+        dark: String,
+    },
+}
+
+impl ThemeChoice {
+    pub fn pick(&self, dark: bool) -> &str {
+        match self {
+            ThemeChoice::Named(name) => name,
+            ThemeChoice::Auto { light, dark: d } => match dark {
+                true => d,
+                false => light,
+            },
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            ThemeChoice::Named(name) => vec![name],
+            ThemeChoice::Auto { light, dark } => vec![light, dark],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct FontConfig {
+    /// A CSS font-family list
+    #[schemars(length(min = 1))]
+    pub family: String,
+    /// Size in CSS pixels
+    #[schemars(range(min = 1.0, max = 200.0))]
+    pub size: f32,
+    #[schemars(range(min = 0.5, max = 4.0))]
+    pub line_height: f32,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        Self {
+            family: "courier new, courier, monospace".into(),
+            size: 15.0,
+            line_height: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct CursorConfig {
+    pub style: CursorStyle,
+    pub blink: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorStyle {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BellStyle {
+    None,
+    /// Flash the screen
+    Visual,
+    Sound,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RightClick {
+    /// The browser's context menu
+    Menu,
+    SelectWord,
+    Paste,
+}
@@ -1,6 +1,6 @@
-use crate::caster::Caster;
 use crate::config::ConfigWatcher;
-use crate::pty::PtyManager;
+use crate::pty::terminal::{DEFAULT_PROFILE, Terminals};
+use crate::sockets::compress::CompressionMode;
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -9,31 +9,157 @@ use memchr::memrchr;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::io::Write;
-use std::{sync::Arc, time::Instant};
-use tokio::sync::RwLock;
+use std::{
+    sync::atomic::AtomicU64,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
 use unicode_width::UnicodeWidthChar;
 
-// app config
-fn default_layout() -> String {
-    "qwerty".into()
-}
-fn default_theme() -> String {
-    "Default".into()
-}
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct AppConfig {
-    #[serde(default = "default_layout")]
-    pub layout: String,
-    #[serde(default = "default_theme")]
-    pub theme: String,
-}
+/// Websocket subprotocol for compact binary client frames.
+pub const BINARY_PROTOCOL: &str = "xterm-rs.binary";
+/// Bumped whenever the websocket messages change incompatibly; announced in `hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Optional capabilities announced in `hello`.
+pub const FEATURES: &[&str] = &[
+    "binary",
+    "marker",
+    "flow-control",
+    "deflate",
+    "input-control",
+    "set-config",
+    "remap",
+];
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "event", rename_all = "lowercase")]
 pub enum ClientMsg {
-    Data { value: String },
-    Resize { value: SttySize },
+    Data {
+        value: String,
+    },
+    Resize {
+        value: SttySize,
+    },
+    Marker {
+        value: String,
+    },
     Heartbeat,
+    Hello {
+        value: ClientHello,
+    },
+    // bytes of binary output the client has finished rendering
+    Ack {
+        value: u64,
+    },
+    // input control: ask for the keyboard, give it up, or (holder only) answer a request
+    #[serde(rename = "request-control")]
+    RequestControl,
+    #[serde(rename = "release-control")]
+    ReleaseControl,
+    #[serde(rename = "grant-control")]
+    GrantControl {
+        value: u64,
+    },
+    #[serde(rename = "deny-control")]
+    DenyControl {
+        value: u64,
+    },
+    // a partial AppConfig, written back to config.toml
+    #[serde(rename = "set-config")]
+    SetConfig {
+        value: serde_json::Value,
+    },
+    // raw bytes from a binary frame, possibly not utf-8
+    #[serde(skip)]
+    Input(Vec<u8>),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ClientHello {
+    pub protocol: u32,
+    #[serde(default)]
+    pub features: Vec<String>,
+    // output compression the client can decode, if any
+    #[serde(default)]
+    pub compression: Option<CompressionMode>,
+}
+
+/// Reported to the client as an `error` event.
+#[derive(Serialize, Debug)]
+pub struct ProtoError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ProtoError {
+    pub fn malformed(message: impl Into<String>) -> Self {
+        Self {
+            code: "malformed",
+            message: message.into(),
+        }
+    }
+    pub fn unsupported(message: impl Into<String>) -> Self {
+        Self {
+            code: "unsupported",
+            message: message.into(),
+        }
+    }
+}
+
+impl ClientMsg {
+    const EVENTS: &[&str] = &[
+        "data",
+        "resize",
+        "marker",
+        "heartbeat",
+        "hello",
+        "ack",
+        "request-control",
+        "release-control",
+        "grant-control",
+        "deny-control",
+        "set-config",
+    ];
+
+    pub fn from_json(buf: &[u8]) -> Result<Self, ProtoError> {
+        serde_json::from_slice(buf).map_err(|e| {
+            let event = serde_json::from_slice::<serde_json::Value>(buf)
+                .ok()
+                .and_then(|v| v.get("event")?.as_str().map(str::to_owned));
+            match event {
+                Some(ev) if !Self::EVENTS.contains(&ev.as_str()) => {
+                    ProtoError::unsupported(format!("unknown event '{ev}'"))
+                }
+                _ => ProtoError::malformed(e.to_string()),
+            }
+        })
+    }
+
+    /// Decodes a binary frame: one type byte followed by the payload.
+    ///   0x00 input     raw bytes
+    ///   0x01 resize    rows u16le, cols u16le
+    ///   0x02 heartbeat
+    ///   0x03 ack       consumed bytes u32le
+    pub fn from_frame(buf: &[u8]) -> Result<Self, ProtoError> {
+        let Some((&kind, payload)) = buf.split_first() else {
+            return Err(ProtoError::malformed("empty frame"));
+        };
+        match (kind, payload) {
+            (0x00, p) => Ok(ClientMsg::Input(p.to_vec())),
+            (0x01, &[r0, r1, c0, c1]) => Ok(ClientMsg::Resize {
+                value: SttySize {
+                    rows: u16::from_le_bytes([r0, r1]),
+                    cols: u16::from_le_bytes([c0, c1]),
+                },
+            }),
+            (0x02, []) => Ok(ClientMsg::Heartbeat),
+            (0x03, &[a, b, c, d]) => Ok(ClientMsg::Ack {
+                value: u32::from_le_bytes([a, b, c, d]).into(),
+            }),
+            (0x00..=0x03, _) => Err(ProtoError::malformed(format!("bad payload for frame type {kind:#04x}"))),
+            _ => Err(ProtoError::unsupported(format!("unknown frame type {kind:#04x}"))),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -42,12 +168,85 @@ pub struct SttySize {
     pub rows: u16,
 }
 
+/// How PTY output is batched into websocket frames for each client.
+#[derive(Debug, Clone)]
+pub struct OutputOpts {
+    pub max_fps: u32,
+    pub max_frame: usize,
+    // queued bytes past which a client skips to the latest screen
+    pub skip_behind: Option<usize>,
+}
+
+/// Server-driven websocket pings; a client silent for `timeout` is closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeepaliveOpts {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// What a websocket client may do with the shared terminal.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Write,
+    // sees output but cannot type or resize
+    View,
+}
+
+/// `?token=` on websocket and api urls.
+#[derive(Deserialize, Debug)]
+pub struct TokenQuery {
+    pub token: Option<String>,
+}
+
+/// `?profile=` on websocket and api urls: which terminal, the default one if absent.
+#[derive(Deserialize, Debug)]
+pub struct ProfileQuery {
+    pub profile: Option<String>,
+}
+
+impl ProfileQuery {
+    pub fn name(&self) -> &str {
+        self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+}
+
+/// Access tokens, passed as `?token=` on the websocket url.
+#[derive(Debug, Clone, Default)]
+pub struct AuthOpts {
+    // when set, writers must present it
+    pub token: Option<String>,
+    pub view_tokens: Vec<String>,
+}
+
+impl AuthOpts {
+    /// The role a token grants, or `None` if the connection must be refused.
+    pub fn role(&self, token: Option<&str>) -> Option<Role> {
+        match (token, &self.token) {
+            (Some(t), Some(w)) if same_token(t, w) => Some(Role::Write),
+            (Some(t), _) if self.view_tokens.iter().any(|v| same_token(v, t)) => Some(Role::View),
+            (_, None) => Some(Role::Write),
+            _ => None,
+        }
+    }
+}
+
+/// Compares tokens in time that depends only on their lengths, so timing does not leak a prefix.
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 pub struct AppState {
     pub start: Instant,
-    pub pty: Arc<PtyManager>,
-    pub caster: Option<Arc<Caster>>,
+    pub next_client: AtomicU64,
+    pub terminals: Terminals,
     pub watcher: ConfigWatcher,
-    pub stty_size: Arc<RwLock<(u16, u16)>>,
+    pub output: OutputOpts,
+    // these two follow the [server] table live
+    pub keepalive: watch::Sender<KeepaliveOpts>,
+    pub auth: std::sync::RwLock<AuthOpts>,
+    // JSON Schema of config.toml, served at /api/config/schema
+    pub schema: serde_json::Value,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -151,6 +350,14 @@ impl RingBytes {
         }
     }
 
+    /// Changes the capacity, dropping the oldest bytes if it shrinks.
+    pub fn set_limit(&mut self, limit: usize) {
+        if let Some(x) = self.buf.len().checked_sub(limit) {
+            self.buf.drain(..x);
+        }
+        self.limit = limit;
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         let (a, b) = self.buf.as_slices();
         let mut v = Vec::with_capacity(self.buf.len());
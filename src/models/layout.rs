@@ -0,0 +1,337 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// `KeyboardEvent.code`s of the keys a layout may move, row by row as on a US keyboard.
+const ROWS: [&[&str]; 4] = [
+    &[
+        "Backquote",
+        "Digit1",
+        "Digit2",
+        "Digit3",
+        "Digit4",
+        "Digit5",
+        "Digit6",
+        "Digit7",
+        "Digit8",
+        "Digit9",
+        "Digit0",
+        "Minus",
+        "Equal",
+    ],
+    &[
+        "KeyQ",
+        "KeyW",
+        "KeyE",
+        "KeyR",
+        "KeyT",
+        "KeyY",
+        "KeyU",
+        "KeyI",
+        "KeyO",
+        "KeyP",
+        "BracketLeft",
+        "BracketRight",
+        "Backslash",
+    ],
+    &[
+        "KeyA",
+        "KeyS",
+        "KeyD",
+        "KeyF",
+        "KeyG",
+        "KeyH",
+        "KeyJ",
+        "KeyK",
+        "KeyL",
+        "Semicolon",
+        "Quote",
+    ],
+    &[
+        "KeyZ", "KeyX", "KeyC", "KeyV", "KeyB", "KeyN", "KeyM", "Comma", "Period", "Slash",
+    ],
+];
+
+// (unshifted, shifted) characters for each of the ROWS
+type Rows = [(&'static str, &'static str); 4];
+
+const US_NUMBERS: (&str, &str) = ("`1234567890-=", "~!@#$%^&*()_+");
+
+const QWERTY: Rows = [
+    US_NUMBERS,
+    ("qwertyuiop[]\\", "QWERTYUIOP{}|"),
+    ("asdfghjkl;'", "ASDFGHJKL:\""),
+    ("zxcvbnm,./", "ZXCVBNM<>?"),
+];
+
+const BUILTIN: &[(&str, Rows)] = &[
+    (
+        "dvorak",
+        [
+            ("`1234567890[]", "~!@#$%^&*(){}"),
+            ("',.pyfgcrl/=\\", "\"<>PYFGCRL?+|"),
+            ("aoeuidhtns-", "AOEUIDHTNS_"),
+            (";qjkxbmwvz", ":QJKXBMWVZ"),
+        ],
+    ),
+    (
+        "colemak",
+        [
+            US_NUMBERS,
+            ("qwfpgjluy;[]\\", "QWFPGJLUY:{}|"),
+            ("arstdhneio'", "ARSTDHNEIO\""),
+            ("zxcvbkm,./", "ZXCVBKM<>?"),
+        ],
+    ),
+    (
+        "colemak-dh",
+        [
+            US_NUMBERS,
+            ("qwfpbjluy;[]\\", "QWFPBJLUY:{}|"),
+            ("arstgmneio'", "ARSTGMNEIO\""),
+            ("zxcdvkh,./", "ZXCDVKH<>?"),
+        ],
+    ),
+    (
+        "workman",
+        [
+            US_NUMBERS,
+            ("qdrwbjfup;[]\\", "QDRWBJFUP:{}|"),
+            ("ashtgyneoi'", "ASHTGYNEOI\""),
+            ("zxmcvkl,./", "ZXMCVKL<>?"),
+        ],
+    ),
+    (
+        "azerty",
+        [
+            ("²&é\"'(-è_çà)=", "²1234567890°+"),
+            ("azertyuiop^$*", "AZERTYUIOP¨£µ"),
+            ("qsdfghjklmù", "QSDFGHJKLM%"),
+            ("wxcvbn,;:!", "WXCVBN?./§"),
+        ],
+    ),
+];
+
+/// What one key types, without and with Shift. In config.toml either `"q"`
+/// (Shift gives the uppercase) or `["q", "Q"]`; always sent to clients as a pair.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(from = "KeySpec")]
+pub struct KeyPair(pub String, pub String);
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum KeySpec {
+    One(String),
+    Pair(String, String),
+}
+
+impl From<KeySpec> for KeyPair {
+    fn from(spec: KeySpec) -> Self {
+        match spec {
+            KeySpec::One(base) => {
+                let shifted = base.to_uppercase();
+                KeyPair(base, shifted)
+            }
+            KeySpec::Pair(base, shifted) => KeyPair(base, shifted),
+        }
+    }
+}
+
+/// A layout: `KeyboardEvent.code` to what the key types. Keys left out type what the OS says.
+pub type KeyMap = BTreeMap<String, KeyPair>;
+
+fn from_rows(rows: &Rows) -> KeyMap {
+    ROWS.iter()
+        .zip(rows)
+        .flat_map(|(codes, (base, shifted))| codes.iter().zip(base.chars().zip(shifted.chars())))
+        .map(|(code, (b, s))| (code.to_string(), KeyPair(b.into(), s.into())))
+        .collect()
+}
+
+pub fn builtin_layouts() -> impl Iterator<Item = &'static str> {
+    std::iter::once("qwerty").chain(BUILTIN.iter().map(|(name, _)| *name))
+}
+
+pub fn is_known_code(code: &str) -> bool {
+    ROWS.iter().any(|row| row.contains(&code))
+}
+
+/// Looks a layout up in config.toml's `[layouts]`, then the built-ins. `qwerty` is the empty
+/// map: the keyboard is taken as it is.
+pub fn find_layout(name: &str, custom: &BTreeMap<String, KeyMap>) -> Option<KeyMap> {
+    if let Some(map) = custom.get(name) {
+        return Some(map.clone());
+    }
+    if name == "qwerty" {
+        return Some(KeyMap::new());
+    }
+    BUILTIN
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, rows)| from_rows(rows))
+}
+
+/// Remaps typed text server-side, for clients that send characters rather than key codes:
+/// each character is taken as typed on a US qwerty keyboard and replaced with what the same
+/// key types in the layout. Escape sequences and bracketed pastes go through untouched.
+#[derive(Debug, Default)]
+pub struct Remapper {
+    chars: HashMap<char, String>,
+    state: Escape,
+    // the escape sequence so far, or the end of a paste; these can span messages
+    seq: String,
+    // a multi-byte character cut off at the end of the last message
+    partial: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Escape {
+    #[default]
+    None,
+    // just after ESC
+    Start,
+    // inside CSI or SS3, until the final byte
+    Sequence,
+    Paste,
+}
+
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+// longer than any sequence a terminal sends; give up on it rather than buffer forever
+const MAX_SEQ: usize = 32;
+
+impl Remapper {
+    pub fn set_layout(&mut self, layout: &KeyMap) {
+        let qwerty = from_rows(&QWERTY);
+        self.chars = layout
+            .iter()
+            .filter_map(|(code, to)| Some((qwerty.get(code)?, to)))
+            .flat_map(|(from, to)| [(&from.0, &to.0), (&from.1, &to.1)])
+            .filter_map(|(from, to)| Some((from.chars().next()?, to.clone())))
+            .collect();
+    }
+
+    pub fn remap(&mut self, bytes: &[u8]) -> Vec<u8> {
+        // nothing to map, but still follow escape sequences in case the layout changes mid-way
+        if self.chars.is_empty() && self.partial.is_empty() && self.state == Escape::None && !bytes.contains(&0x1b) {
+            return bytes.to_vec();
+        }
+        let mut input = std::mem::take(&mut self.partial);
+        input.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&input) {
+            Ok(_) => input.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            // not text; leave it alone
+            Err(_) => return input,
+        };
+        self.partial = input.split_off(valid);
+        let text = String::from_utf8(input).expect("checked above");
+
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if self.state == Escape::None {
+                match self.chars.get(&c) {
+                    Some(to) => out.push_str(to),
+                    None => out.push(c),
+                }
+                if c == '\x1b' {
+                    self.seq.push(c);
+                    self.state = Escape::Start;
+                }
+                continue;
+            }
+            out.push(c);
+            self.seq.push(c);
+            let last = ('\x40'..='\x7e').contains(&c);
+            self.state = match self.state {
+                Escape::Start if c == '[' || c == 'O' => Escape::Sequence,
+                Escape::Sequence if last && self.seq == PASTE_START => Escape::Paste,
+                Escape::Sequence if !last && self.seq.len() <= MAX_SEQ => Escape::Sequence,
+                Escape::Paste if !self.seq.ends_with(PASTE_END) => Escape::Paste,
+                _ => Escape::None,
+            };
+            match self.state {
+                Escape::Sequence => {}
+                // keep just enough to spot the end marker
+                Escape::Paste => {
+                    while self.seq.len() >= PASTE_END.len() {
+                        self.seq.remove(0);
+                    }
+                }
+                _ => self.seq.clear(),
+            }
+        }
+        out.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remapper(layout: &str) -> Remapper {
+        let mut r = Remapper::default();
+        r.set_layout(&find_layout(layout, &BTreeMap::new()).unwrap());
+        r
+    }
+
+    // name, the messages sent, what comes out
+    type Case = (&'static str, &'static [&'static [u8]], &'static [u8]);
+
+    fn feed(r: &mut Remapper, chunks: &[&[u8]]) -> Vec<u8> {
+        chunks.iter().flat_map(|chunk| r.remap(chunk)).collect()
+    }
+
+    #[test]
+    fn builtin_rows_fill_every_key() {
+        let layouts = std::iter::once(("qwerty", QWERTY)).chain(BUILTIN.iter().copied());
+        for (name, rows) in layouts {
+            for (i, (codes, (base, shifted))) in ROWS.iter().zip(rows).enumerate() {
+                assert_eq!(base.chars().count(), codes.len(), "{name} row {i}");
+                assert_eq!(shifted.chars().count(), codes.len(), "{name} row {i}, shifted");
+            }
+        }
+    }
+
+    #[test]
+    fn remaps_across_messages() {
+        // dvorak: qwerty d types e, b types x, c types j
+        let cases: &[Case] = &[
+            ("plain text", &[b"dbc"], b"exj"),
+            ("csi in one message", &[b"\x1b[1;5Dd"], b"\x1b[1;5De"),
+            ("csi split after ESC", &[b"\x1b", b"[1;5D", b"d"], b"\x1b[1;5De"),
+            ("csi split mid-parameters", &[b"\x1b[1;", b"5Dd"], b"\x1b[1;5De"),
+            ("ss3", &[b"\x1bO", b"Dd"], b"\x1bODe"),
+            ("paste", &[b"\x1b[200~dbc\x1b[201~d"], b"\x1b[200~dbc\x1b[201~e"),
+            (
+                "paste split across messages",
+                &[b"\x1b[20", b"0~db", b"c\x1b[2", b"01~d"],
+                b"\x1b[200~dbc\x1b[201~e",
+            ),
+            ("multibyte char split", &[b"d\xc3", b"\xa9d"], "eée".as_bytes()),
+            (
+                "multibyte char split in three",
+                &[b"\xe2", b"\x82", b"\xacd"],
+                "€e".as_bytes(),
+            ),
+        ];
+        for (name, chunks, want) in cases {
+            let mut r = remapper("dvorak");
+            assert_eq!(feed(&mut r, chunks), *want, "{name}");
+        }
+    }
+
+    #[test]
+    fn layout_change_keeps_escape_state() {
+        let mut r = remapper("qwerty");
+        assert_eq!(r.remap(b"d\x1b[1;"), b"d\x1b[1;");
+        r.set_layout(&find_layout("dvorak", &BTreeMap::new()).unwrap());
+        assert_eq!(r.remap(b"5Dd"), b"5De");
+
+        let mut r = remapper("dvorak");
+        assert_eq!(r.remap(b"\x1b[200~d"), b"\x1b[200~d");
+        r.set_layout(&find_layout("colemak", &BTreeMap::new()).unwrap());
+        // colemak: qwerty d types s
+        assert_eq!(r.remap(b"d\x1b[201~d"), b"d\x1b[201~s");
+    }
+}
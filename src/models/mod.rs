@@ -1,2 +1,10 @@
+pub mod app_config;
 pub mod common;
-pub use common::{AppError, AppState, AppConfig, ClientMsg, buf_trim, logger, RingBytes};
+pub mod layout;
+pub mod theme;
+pub use app_config::{AppConfig, ShellProfile};
+pub use common::{
+    AppError, AppState, AuthOpts, BINARY_PROTOCOL, ClientMsg, FEATURES, KeepaliveOpts, OutputOpts, PROTOCOL_VERSION,
+    ProfileQuery, ProtoError, RingBytes, Role, TokenQuery, buf_trim, logger,
+};
+pub use theme::{THEMES_FILE, Theme, is_hex_color, load_builtin_themes};
@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+pub const THEMES_FILE: &str = "js/themes.min.mjs";
+
+// xterm.js defaults, used where a theme leaves a color unset
+const DEFAULT_FG: &str = "#ffffff";
+const DEFAULT_BG: &str = "#000000";
+const DEFAULT_ANSI: [&str; 16] = [
+    "#2e3436", "#cc0000", "#4e9a06", "#c4a000", "#3465a4", "#75507b", "#06989a", "#d3d7cf", "#555753", "#ef2929",
+    "#8ae234", "#fce94f", "#729fcf", "#ad7fa8", "#34e2e2", "#eeeeec",
+];
+
+/// A palette in xterm.js `ITheme` form. Config files may spell the keys in snake_case.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Theme {
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub cursor: Option<String>,
+    #[serde(alias = "selection", alias = "selection_background")]
+    pub selection_background: Option<String>,
+    pub black: Option<String>,
+    pub red: Option<String>,
+    pub green: Option<String>,
+    pub yellow: Option<String>,
+    pub blue: Option<String>,
+    pub magenta: Option<String>,
+    pub cyan: Option<String>,
+    pub white: Option<String>,
+    #[serde(alias = "bright_black")]
+    pub bright_black: Option<String>,
+    #[serde(alias = "bright_red")]
+    pub bright_red: Option<String>,
+    #[serde(alias = "bright_green")]
+    pub bright_green: Option<String>,
+    #[serde(alias = "bright_yellow")]
+    pub bright_yellow: Option<String>,
+    #[serde(alias = "bright_blue")]
+    pub bright_blue: Option<String>,
+    #[serde(alias = "bright_magenta")]
+    pub bright_magenta: Option<String>,
+    #[serde(alias = "bright_cyan")]
+    pub bright_cyan: Option<String>,
+    #[serde(alias = "bright_white")]
+    pub bright_white: Option<String>,
+}
+
+impl Theme {
+    pub fn fg(&self) -> &str {
+        self.foreground.as_deref().unwrap_or(DEFAULT_FG)
+    }
+
+    pub fn bg(&self) -> &str {
+        self.background.as_deref().unwrap_or(DEFAULT_BG)
+    }
+
+    pub fn cursor(&self) -> &str {
+        self.cursor.as_deref().unwrap_or_else(|| self.fg())
+    }
+
+    /// ANSI colors 0-15 in xterm order.
+    pub fn ansi(&self) -> [&str; 16] {
+        let named = [
+            &self.black,
+            &self.red,
+            &self.green,
+            &self.yellow,
+            &self.blue,
+            &self.magenta,
+            &self.cyan,
+            &self.white,
+            &self.bright_black,
+            &self.bright_red,
+            &self.bright_green,
+            &self.bright_yellow,
+            &self.bright_blue,
+            &self.bright_magenta,
+            &self.bright_cyan,
+            &self.bright_white,
+        ];
+        std::array::from_fn(|i| named[i].as_deref().unwrap_or(DEFAULT_ANSI[i]))
+    }
+
+    /// The colors this theme sets, by config key.
+    pub fn colors(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("foreground", &self.foreground),
+            ("background", &self.background),
+            ("cursor", &self.cursor),
+            ("selection_background", &self.selection_background),
+            ("black", &self.black),
+            ("red", &self.red),
+            ("green", &self.green),
+            ("yellow", &self.yellow),
+            ("blue", &self.blue),
+            ("magenta", &self.magenta),
+            ("cyan", &self.cyan),
+            ("white", &self.white),
+            ("bright_black", &self.bright_black),
+            ("bright_red", &self.bright_red),
+            ("bright_green", &self.bright_green),
+            ("bright_yellow", &self.bright_yellow),
+            ("bright_blue", &self.bright_blue),
+            ("bright_magenta", &self.bright_magenta),
+            ("bright_cyan", &self.bright_cyan),
+            ("bright_white", &self.bright_white),
+        ]
+        .into_iter()
+        .filter_map(|(key, color)| Some((key, color.as_deref()?)))
+    }
+}
+
+/// `#rgb`, `#rrggbb` or `#rrggbbaa`.
+pub fn is_hex_color(s: &str) -> bool {
+    s.strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Parses the `Name={key:"#rrggbb",...}` objects out of the minified theme bundle.
+pub fn parse_themes(src: &str) -> BTreeMap<String, Theme> {
+    let mut out = BTreeMap::new();
+    let mut rest = src;
+
+    while let Some(eq) = rest.find("={") {
+        let name_start = rest[..eq]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let name = &rest[name_start..eq];
+        let Some(close) = rest[eq..].find('}') else {
+            break;
+        };
+        let body = &rest[eq + 2..eq + close];
+
+        let fields: serde_json::Map<String, serde_json::Value> = body
+            .split(',')
+            .filter_map(|kv| kv.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), serde_json::from_str(v.trim()).unwrap_or_default()))
+            .collect();
+        if let Ok(theme) = serde_json::from_value(serde_json::Value::Object(fields)) {
+            out.insert(name.to_string(), theme);
+        }
+        rest = &rest[eq + close..];
+    }
+    out
+}
+
+pub fn load_builtin_themes(resource: &Path) -> Result<BTreeMap<String, Theme>> {
+    let path = resource.join(THEMES_FILE);
+    let src = std::fs::read_to_string(&path).with_context(|| format!("read {:?}", path))?;
+    Ok(parse_themes(&src))
+}
@@ -0,0 +1,71 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+#[derive(Default)]
+struct Counts {
+    clients: usize,
+    paused: usize,
+}
+
+/// Tracks attached clients and how many of them asked to pause.
+/// The PTY reader stops while every attached client is paused, so the shell blocks on write.
+#[derive(Default)]
+pub struct FlowGate {
+    counts: Mutex<Counts>,
+    open: Condvar,
+}
+
+impl FlowGate {
+    /// Blocks the calling (reader) thread while all clients are paused.
+    pub fn wait_open(&self) {
+        let counts = self.counts.lock().unwrap();
+        let _guard = self
+            .open
+            .wait_while(counts, |c| c.clients > 0 && c.paused == c.clients)
+            .unwrap();
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Counts)) {
+        f(&mut self.counts.lock().unwrap());
+        self.open.notify_all();
+    }
+}
+
+/// One client's registration with the gate; unregisters on drop.
+pub struct FlowHandle {
+    gate: Arc<FlowGate>,
+    paused: bool,
+}
+
+impl FlowHandle {
+    pub fn new(gate: Arc<FlowGate>) -> Self {
+        gate.update(|c| c.clients += 1);
+        Self { gate, paused: false }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused == paused {
+            return;
+        }
+        self.paused = paused;
+        self.gate.update(|c| match paused {
+            true => c.paused += 1,
+            false => c.paused -= 1,
+        });
+    }
+}
+
+impl Drop for FlowHandle {
+    fn drop(&mut self) {
+        let paused = self.paused;
+        self.gate.update(|c| {
+            c.clients -= 1;
+            if paused {
+                c.paused -= 1;
+            }
+        });
+    }
+}
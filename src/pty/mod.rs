@@ -1,2 +1,7 @@
+mod flow;
 mod pty_manager;
-pub use pty_manager::PtyManager;
+mod size;
+pub mod terminal;
+pub use flow::FlowHandle;
+pub use pty_manager::{PtyManager, ShellSpec};
+pub use size::{SizeArbiter, SizePolicy};
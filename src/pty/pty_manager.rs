@@ -1,27 +1,50 @@
+use super::flow::{FlowGate, FlowHandle};
 use crate::models::RingBytes;
 use anyhow::{Context, Result};
 use portable_pty::*;
 use std::{
+    collections::BTreeMap,
     io::{Read, Write},
-    sync::Arc,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 use tokio::{
-    sync::{Mutex, broadcast},
+    sync::{Mutex, broadcast, watch},
     task,
 };
 
 const BUF_SIZE: usize = 4096;
 
+/// What runs in the terminal; started again from the current spec whenever it exits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    // on top of the server's own environment
+    pub env: BTreeMap<String, String>,
+    pub term: String,
+}
+
 pub struct PtyManager {
     tx: broadcast::Sender<Vec<u8>>,
     history: Arc<Mutex<RingBytes>>,
     writer: Arc<Mutex<Box<dyn Write + Send>>>,
     master: Arc<Mutex<Box<dyn MasterPty + Send>>>,
     size: Arc<Mutex<PtySize>>,
+    respawns: watch::Sender<u32>,
+    flow: Arc<FlowGate>,
+    spec: Arc<std::sync::RwLock<ShellSpec>>,
+    // set by `close`: the shell is killed and not started again
+    closed: Arc<AtomicBool>,
+    killer: Arc<std::sync::Mutex<Box<dyn ChildKiller + Send + Sync>>>,
 }
 
 impl PtyManager {
-    pub async fn new(rows: u16, cols: u16, history_limit: usize) -> Result<Self> {
+    pub async fn new(rows: u16, cols: u16, history_limit: usize, spec: ShellSpec) -> Result<Self> {
         let (tx, _) = broadcast::channel::<Vec<u8>>(4096);
         let history = Arc::new(Mutex::new(RingBytes::new(history_limit)));
         let size = Arc::new(Mutex::new(PtySize {
@@ -31,31 +54,69 @@ impl PtyManager {
             pixel_height: 0,
         }));
 
-        let (writer, master, _child) = Self::spawn_shell(&size).await?;
+        let (writer, master, child) = Self::spawn_shell(&size, &spec).await?;
+        let spec = Arc::new(std::sync::RwLock::new(spec));
+        let killer = Arc::new(std::sync::Mutex::new(child.clone_killer()));
         let writer = Arc::new(Mutex::new(writer));
         let master = Arc::new(Mutex::new(master));
+        let (respawns, _) = watch::channel(0);
+        let flow = Arc::new(FlowGate::default());
 
-        Self::launch_reader(
-            tx.clone(),
-            Arc::clone(&history),
-            Arc::clone(&writer),
-            Arc::clone(&master),
-            Arc::clone(&size),
-        );
-
-        Ok(Self {
+        let pty = Self {
             tx,
             history,
             writer,
             master,
             size,
-        })
+            respawns,
+            flow,
+            spec,
+            closed: Arc::default(),
+            killer,
+        };
+        pty.launch_reader();
+        Ok(pty)
+    }
+
+    pub fn spec(&self) -> ShellSpec {
+        self.spec.read().unwrap().clone()
+    }
+
+    /// Used from the next time the shell starts; the running one is left alone.
+    /// Returns whether the spec changed.
+    pub fn set_spec(&self, spec: ShellSpec) -> bool {
+        let mut cur = self.spec.write().unwrap();
+        let changed = *cur != spec;
+        *cur = spec;
+        changed
+    }
+
+    /// Kills the shell for good; clients see the message and then no more output.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Err(e) = self.killer.lock().unwrap().kill() {
+            crate::models::logger("error", format!("Failed to kill shell: {}", e));
+        }
     }
 
     pub async fn subscribe(&self) -> (broadcast::Receiver<Vec<u8>>, RingBytes) {
         (self.tx.subscribe(), self.history.lock().await.clone())
     }
 
+    pub async fn set_history_limit(&self, limit: usize) {
+        self.history.lock().await.set_limit(limit);
+    }
+
+    /// Counts shell restarts after the process exits.
+    pub fn respawns(&self) -> watch::Receiver<u32> {
+        self.respawns.subscribe()
+    }
+
+    /// Registers a client for flow control; reading stops while all registered clients are paused.
+    pub fn flow_handle(&self) -> FlowHandle {
+        FlowHandle::new(Arc::clone(&self.flow))
+    }
+
     pub async fn write(&self, bytes: &[u8]) -> Result<()> {
         let mut writer = self.writer.lock().await;
         writer.write_all(bytes)?;
@@ -76,34 +137,50 @@ impl PtyManager {
 
     async fn spawn_shell(
         size: &Arc<Mutex<PtySize>>,
+        spec: &ShellSpec,
     ) -> Result<(Box<dyn Write + Send>, Box<dyn MasterPty + Send>, Box<dyn Child + Send>)> {
         let sz = *size.lock().await;
         let pty_system = native_pty_system();
         let pair = pty_system.openpty(sz).context("open pty")?;
 
-        let mut cmd = CommandBuilder::new("/bin/bash");
+        let mut cmd = CommandBuilder::new(&spec.program);
+        cmd.args(&spec.args);
+        if let Some(cwd) = &spec.cwd {
+            cmd.cwd(cwd);
+        }
         cmd.env("LC_CTYPE", "C.UTF-8");
-        cmd.env("TERM", "xterm-color");
+        cmd.env("TERM", &spec.term);
         cmd.env("COLORTERM", "truecolor");
+        for (key, value) in &spec.env {
+            cmd.env(key, value);
+        }
 
-        let child = pair.slave.spawn_command(cmd).context("spawn shell")?;
+        let child = pair
+            .slave
+            .spawn_command(cmd)
+            .with_context(|| format!("spawn {}", spec.program))?;
         let writer = pair.master.take_writer().context("take writer")?;
         Ok((writer, pair.master, child))
     }
 
-    fn launch_reader(
-        tx: broadcast::Sender<Vec<u8>>,
-        history: Arc<Mutex<RingBytes>>,
-        writer: Arc<Mutex<Box<dyn Write + Send>>>,
-        master: Arc<Mutex<Box<dyn MasterPty + Send>>>,
-        size: Arc<Mutex<PtySize>>,
-    ) {
+    fn launch_reader(&self) {
+        let tx = self.tx.clone();
+        let history = Arc::clone(&self.history);
+        let writer = Arc::clone(&self.writer);
+        let master = Arc::clone(&self.master);
+        let size = Arc::clone(&self.size);
+        let respawns = self.respawns.clone();
+        let flow = Arc::clone(&self.flow);
+        let spec = Arc::clone(&self.spec);
+        let closed = Arc::clone(&self.closed);
+        let killer = Arc::clone(&self.killer);
         task::spawn_blocking(move || {
             loop {
                 let mut reader = master.blocking_lock().try_clone_reader().expect("clone reader");
 
                 let mut buf = [0u8; BUF_SIZE];
                 loop {
+                    flow.wait_open();
                     match reader.read(&mut buf) {
                         Ok(0) => break,
                         Ok(n) => {
@@ -117,15 +194,24 @@ impl PtyManager {
                 }
 
                 const COMPLETED: &[u8] = b"[Process completed]\r\n\r\n";
-                {
-                    history.blocking_lock().extend(COMPLETED);
-                    let _ = tx.send(COMPLETED.to_vec());
+                const CLOSED: &[u8] = b"\r\n[Terminal closed]\r\n";
+                let msg = match closed.load(Ordering::SeqCst) {
+                    true => CLOSED,
+                    false => COMPLETED,
+                };
+                history.blocking_lock().extend(msg);
+                let _ = tx.send(msg.to_vec());
+                if closed.load(Ordering::SeqCst) {
+                    break;
                 }
 
-                match tokio::runtime::Handle::current().block_on(Self::spawn_shell(&size)) {
-                    Ok((new_writer, new_master, _new_child)) => {
+                let spec = spec.read().unwrap().clone();
+                match tokio::runtime::Handle::current().block_on(Self::spawn_shell(&size, &spec)) {
+                    Ok((new_writer, new_master, new_child)) => {
                         *writer.blocking_lock() = new_writer;
                         *master.blocking_lock() = new_master;
+                        *killer.lock().unwrap() = new_child.clone_killer();
+                        respawns.send_modify(|n| *n += 1);
                     }
                     Err(e) => {
                         let msg = format!("[Respawn failed: {e}]\r\n").into_bytes();
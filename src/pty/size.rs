@@ -0,0 +1,78 @@
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// How the shared PTY size is picked when several clients report different sizes.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizePolicy {
+    /// the client that resized last wins
+    Latest,
+    /// the smallest rows and columns of all clients, so every client sees the whole screen
+    Smallest,
+    /// the longest-connected client decides; the next oldest takes over when it leaves
+    Owner,
+}
+
+#[derive(Default)]
+struct Sizes {
+    // client id -> (rows, cols); ids grow with connection order
+    clients: BTreeMap<u64, (u16, u16)>,
+    latest: Option<u64>,
+}
+
+/// Collects the size every client asks for and decides the effective PTY size.
+pub struct SizeArbiter {
+    policy: SizePolicy,
+    sizes: Mutex<Sizes>,
+    effective: watch::Sender<(u16, u16)>,
+}
+
+impl SizeArbiter {
+    pub fn new(policy: SizePolicy, initial: (u16, u16)) -> Self {
+        Self {
+            policy,
+            sizes: Mutex::default(),
+            effective: watch::Sender::new(initial),
+        }
+    }
+
+    /// Effective (rows, cols); changes whenever the arbitration result does.
+    pub fn subscribe(&self) -> watch::Receiver<(u16, u16)> {
+        self.effective.subscribe()
+    }
+
+    /// Records a client's requested size; returns the new effective size if it changed.
+    pub fn update(&self, client: u64, rows: u16, cols: u16) -> Option<(u16, u16)> {
+        let mut sizes = self.sizes.lock().unwrap();
+        sizes.clients.insert(client, (rows, cols));
+        sizes.latest = Some(client);
+        self.publish(&sizes)
+    }
+
+    /// Forgets a disconnected client; returns the new effective size if it changed.
+    pub fn remove(&self, client: u64) -> Option<(u16, u16)> {
+        let mut sizes = self.sizes.lock().unwrap();
+        sizes.clients.remove(&client)?;
+        if sizes.latest == Some(client) {
+            sizes.latest = None;
+        }
+        self.publish(&sizes)
+    }
+
+    fn publish(&self, sizes: &Sizes) -> Option<(u16, u16)> {
+        let size = match self.policy {
+            SizePolicy::Latest => sizes.latest.and_then(|c| sizes.clients.get(&c).copied()),
+            SizePolicy::Smallest => sizes
+                .clients
+                .values()
+                .copied()
+                .reduce(|(r1, c1), (r2, c2)| (r1.min(r2), c1.min(c2))),
+            SizePolicy::Owner => sizes.clients.values().next().copied(),
+        }?;
+        let changed = self
+            .effective
+            .send_if_modified(|cur| std::mem::replace(cur, size) != size);
+        changed.then_some(size)
+    }
+}
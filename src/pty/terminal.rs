@@ -0,0 +1,255 @@
+use super::{PtyManager, ShellSpec, SizeArbiter, SizePolicy};
+use crate::caster::Caster;
+use crate::caster::sink::{CastSink, SinkSpec, build_sink};
+use crate::config::ConfigWatcher;
+use crate::models::{AppConfig, AppState, ShellProfile, logger};
+use crate::sockets::presence::ClientRegistry;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock, broadcast, watch};
+
+/// The profile of the terminal at `/` and `/ws`; `[profiles.default]` in config.toml overrides it.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// One shared terminal: a shell started from a profile, with its own clients, size and recording.
+pub struct Terminal {
+    pub profile: String,
+    pub start: Instant,
+    pub session: u128, // start timestamp, unix millis; also names the cast file
+    pub pty: Arc<PtyManager>,
+    pub caster: Arc<Caster>,
+    pub stty_size: Arc<RwLock<(u16, u16)>>, // effective size, as decided by `sizes`
+    pub sizes: SizeArbiter,
+    pub clients: Arc<ClientRegistry>,
+    // set once the profile is removed from config.toml; clients then disconnect
+    pub closed: watch::Sender<bool>,
+}
+
+/// How new terminals are set up; the last three follow the `[server]` table live.
+#[derive(Clone)]
+pub struct TerminalOpts {
+    pub command: String,
+    pub rows: u16,
+    pub cols: u16,
+    pub size_policy: SizePolicy,
+    pub log_dir: PathBuf,
+    pub sinks: Vec<SinkSpec>,
+    pub spool_dir: PathBuf,
+    pub history_limit: usize,
+    pub log_level: u8,
+    pub verbose_interval: u32,
+}
+
+/// The terminals started so far, by profile.
+pub struct Terminals {
+    opts: std::sync::Mutex<TerminalOpts>,
+    watcher: ConfigWatcher,
+    open: Mutex<BTreeMap<String, Arc<Terminal>>>,
+}
+
+impl Terminals {
+    pub fn new(opts: TerminalOpts, watcher: ConfigWatcher) -> Self {
+        Self {
+            opts: std::sync::Mutex::new(opts),
+            watcher,
+            open: Mutex::default(),
+        }
+    }
+
+    /// A running terminal, without starting it.
+    pub async fn get(&self, profile: &str) -> Option<Arc<Terminal>> {
+        self.open.lock().await.get(profile).cloned()
+    }
+
+    /// The profile's terminal, started on first use; `None` if config.toml has no such profile.
+    pub async fn open(&self, profile: &str) -> Result<Option<Arc<Terminal>>> {
+        let mut open = self.open.lock().await;
+        if let Some(term) = open.get(profile) {
+            return Ok(Some(Arc::clone(term)));
+        }
+        let Some(shell) = find_profile(&self.watcher.current(), profile) else {
+            return Ok(None);
+        };
+        let term = Arc::new(self.start(profile, &shell).await?);
+        logger(
+            "info",
+            format!(
+                "Started terminal '{}' ({}), session {}",
+                profile,
+                term.pty.spec().program,
+                term.session
+            ),
+        );
+        open.insert(profile.to_string(), Arc::clone(&term));
+        Ok(Some(term))
+    }
+
+    /// Follows `[profiles]`: an edited profile applies the next time its shell starts,
+    /// and the terminal of a removed one is closed.
+    pub async fn sync_profiles(&self, cfg: &AppConfig) {
+        let opts = self.opts.lock().unwrap().clone();
+        self.open
+            .lock()
+            .await
+            .retain(|name, term| match find_profile(cfg, name) {
+                Some(shell) => {
+                    if term.pty.set_spec(shell_spec(&opts, &shell)) {
+                        logger(
+                            "info",
+                            format!("Profile '{}' changed; applies when its shell restarts", name),
+                        );
+                    }
+                    true
+                }
+                None => {
+                    logger("info", format!("Profile '{}' was removed; closing its terminal", name));
+                    term.caster
+                        .marker(term.start.elapsed().as_secs_f32(), "profile removed");
+                    term.pty.close();
+                    term.closed.send_replace(true);
+                    false
+                }
+            });
+    }
+
+    pub async fn all(&self) -> Vec<Arc<Terminal>> {
+        self.open.lock().await.values().cloned().collect()
+    }
+
+    pub async fn set_history_limit(&self, limit: usize) {
+        self.opts.lock().unwrap().history_limit = limit;
+        for term in self.all().await {
+            term.pty.set_history_limit(limit).await;
+        }
+    }
+
+    pub async fn set_log_level(&self, log_level: u8) {
+        self.opts.lock().unwrap().log_level = log_level;
+        for term in self.all().await {
+            term.caster.set_log_level(log_level);
+        }
+    }
+
+    pub async fn set_flush_interval(&self, secs: u32) {
+        self.opts.lock().unwrap().verbose_interval = secs;
+        for term in self.all().await {
+            term.caster.set_flush_interval(secs);
+        }
+    }
+
+    async fn start(&self, profile: &str, shell: &ShellProfile) -> Result<Terminal> {
+        let opts = self.opts.lock().unwrap().clone();
+        let spec = shell_spec(&opts, shell);
+        let (rows, cols) = (shell.rows.unwrap_or(opts.rows), shell.cols.unwrap_or(opts.cols));
+
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_millis();
+        let start = Instant::now();
+
+        // stdout (log_level 2) and the cast file are managed by the caster, so log_level can change live
+        let mut sinks: Vec<Box<dyn CastSink>> = Vec::new();
+        for sink in &opts.sinks {
+            sinks.push(build_sink(sink, session, &opts.spool_dir)?);
+        }
+        let caster = Caster::new(
+            opts.log_dir,
+            start,
+            session,
+            sinks,
+            opts.log_level,
+            opts.verbose_interval,
+            (rows, cols),
+        )?;
+        // only once recording is set up, so a failure there leaves no shell behind
+        let pty = Arc::new(PtyManager::new(rows, cols, opts.history_limit, spec).await?);
+        spawn_output_recorder(Arc::clone(&caster), start, &pty).await;
+        spawn_auto_markers(Arc::clone(&caster), start, self.watcher.subscribe(), pty.respawns());
+
+        Ok(Terminal {
+            profile: profile.to_string(),
+            start,
+            session,
+            pty,
+            caster,
+            stty_size: Arc::new(RwLock::new((rows, cols))),
+            sizes: SizeArbiter::new(opts.size_policy, (rows, cols)),
+            clients: Arc::default(),
+            closed: watch::Sender::new(false),
+        })
+    }
+}
+
+/// Keeps the open terminals in step with `[profiles]` in config.toml.
+pub fn spawn_profile_sync(state: Arc<AppState>) {
+    let mut cfg_rx = state.watcher.subscribe();
+    tokio::spawn(async move {
+        while cfg_rx.changed().await.is_ok() {
+            let cfg = cfg_rx.borrow_and_update().clone();
+            state.terminals.sync_profiles(&cfg).await;
+        }
+    });
+}
+
+/// The profile as configured; `default` exists even when config.toml does not define it.
+fn find_profile(cfg: &AppConfig, name: &str) -> Option<ShellProfile> {
+    match cfg.profiles.get(name) {
+        Some(shell) => Some(shell.clone()),
+        None if name == DEFAULT_PROFILE => Some(ShellProfile::default()),
+        None => None,
+    }
+}
+
+fn shell_spec(opts: &TerminalOpts, shell: &ShellProfile) -> ShellSpec {
+    ShellSpec {
+        program: shell.command.clone().unwrap_or_else(|| opts.command.clone()),
+        args: shell.args.clone(),
+        cwd: shell.cwd.as_deref().map(expand_home),
+        env: shell.env.clone(),
+        term: shell.term.clone().unwrap_or_else(|| "xterm-color".into()),
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ if path == "~" => std::env::var_os("HOME").map_or_else(|| path.into(), PathBuf::from),
+        _ => path.into(),
+    }
+}
+
+/// Records PTY output once, independent of how many clients are attached or paused.
+async fn spawn_output_recorder(caster: Arc<Caster>, start: Instant, pty: &PtyManager) {
+    let (mut rx, _) = pty.subscribe().await;
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(bytes) => caster.output(start.elapsed().as_secs_f32(), bytes),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Drops chapter markers into the recording for server-side lifecycle events.
+fn spawn_auto_markers(
+    caster: Arc<Caster>,
+    start: Instant,
+    mut cfg_rx: watch::Receiver<AppConfig>,
+    mut respawn_rx: watch::Receiver<u32>,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Ok(()) = cfg_rx.changed() => caster.marker(start.elapsed().as_secs_f32(), "config changed"),
+                Ok(()) = respawn_rx.changed() => caster.marker(start.elapsed().as_secs_f32(), "shell respawned"),
+                else => break,
+            }
+        }
+    });
+}
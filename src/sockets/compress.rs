@@ -0,0 +1,40 @@
+use anyhow::Result;
+use flate2::{Compress, Compression, FlushCompress};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionMode {
+    // one raw deflate stream per connection, sync-flushed per frame (DecompressionStream("deflate-raw"))
+    Deflate,
+}
+
+pub enum Codec {
+    Deflate(Compress),
+}
+
+impl Codec {
+    pub fn new(mode: CompressionMode) -> Self {
+        match mode {
+            CompressionMode::Deflate => Codec::Deflate(Compress::new(Compression::fast(), false)),
+        }
+    }
+
+    pub fn encode(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::Deflate(c) => {
+                let start = c.total_in();
+                let mut out = Vec::with_capacity(data.len() / 2 + 64);
+                loop {
+                    let consumed = (c.total_in() - start) as usize;
+                    c.compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)?;
+                    // the flush is complete once all input is in and the output buffer was not filled
+                    if (c.total_in() - start) as usize == data.len() && out.len() < out.capacity() {
+                        return Ok(out);
+                    }
+                    out.reserve(out.capacity().max(64));
+                }
+            }
+        }
+    }
+}
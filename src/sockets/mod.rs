@@ -1,4 +1,6 @@
+pub mod compress;
+pub mod presence;
 pub mod socket_handler;
 pub mod socket_handler_debug;
-pub use socket_handler::ws_handler;
+pub use socket_handler::{ws_handler, ws_handler_view};
 pub use socket_handler_debug::ws_handler_debug;
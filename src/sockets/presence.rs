@@ -0,0 +1,184 @@
+use crate::models::Role;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+// a holder silent this long loses the keyboard to the next request without being asked
+const CONTROL_IDLE_MS: u128 = 60_000;
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_millis()
+}
+
+/// One attached websocket client, as listed by `/api/clients` and announced in `join`.
+#[derive(Serialize, Debug, Clone)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub user_agent: Option<String>,
+    pub role: Role,
+    pub connected: u128,   // unix millis
+    pub last_active: u128, // unix millis, last message from the client
+}
+
+#[derive(Debug, Clone)]
+pub enum Presence {
+    Join(ClientInfo),
+    Leave(u64),
+    // the keyboard changed hands
+    Control(Option<u64>),
+    ControlRequest { from: u64, to: u64 },
+    ControlDenied(u64),
+}
+
+#[derive(Default)]
+struct Clients {
+    clients: BTreeMap<u64, ClientInfo>,
+    // the one client whose input reaches the PTY
+    holder: Option<u64>,
+}
+
+/// Clients attached to the shared terminal and who holds the keyboard;
+/// changes are broadcast to every session.
+pub struct ClientRegistry {
+    inner: Mutex<Clients>,
+    tx: broadcast::Sender<Presence>,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::default(),
+            tx: broadcast::channel(64).0,
+        }
+    }
+}
+
+impl ClientRegistry {
+    /// Registers a client until the returned guard is dropped.
+    pub fn join(self: &Arc<Self>, id: u64, addr: SocketAddr, user_agent: Option<String>, role: Role) -> Registration {
+        let now = unix_millis();
+        let info = ClientInfo {
+            id,
+            addr,
+            user_agent,
+            role,
+            connected: now,
+            last_active: now,
+        };
+        self.inner.lock().unwrap().clients.insert(id, info.clone());
+        let _ = self.tx.send(Presence::Join(info));
+        Registration {
+            registry: Arc::clone(self),
+            id,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Presence> {
+        self.tx.subscribe()
+    }
+
+    pub fn list(&self) -> Vec<ClientInfo> {
+        self.inner.lock().unwrap().clients.values().cloned().collect()
+    }
+
+    pub fn touch(&self, id: u64) {
+        if let Some(info) = self.inner.lock().unwrap().clients.get_mut(&id) {
+            info.last_active = unix_millis();
+        }
+    }
+
+    pub fn holder(&self) -> Option<u64> {
+        self.inner.lock().unwrap().holder
+    }
+
+    /// Whether `id` may type; a free keyboard goes to whoever types first.
+    pub fn try_input(&self, id: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.holder {
+            Some(holder) => holder == id,
+            None => {
+                inner.set_holder(Some(id), &self.tx);
+                true
+            }
+        }
+    }
+
+    /// Takes a free (or abandoned) keyboard, otherwise asks the holder.
+    pub fn request_control(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.holder {
+            Some(holder) if holder == id => {}
+            Some(holder) if !inner.is_idle(holder) => {
+                let _ = self.tx.send(Presence::ControlRequest { from: id, to: holder });
+            }
+            _ => inner.set_holder(Some(id), &self.tx),
+        }
+    }
+
+    pub fn release_control(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.holder == Some(id) {
+            inner.set_holder(None, &self.tx);
+        }
+    }
+
+    /// Hands the keyboard from the holder `id` to another writer.
+    pub fn grant_control(&self, id: u64, to: u64) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.holder != Some(id) {
+            return Err("only the client holding the keyboard can grant it");
+        }
+        match inner.clients.get(&to) {
+            Some(info) if info.role == Role::Write => {
+                inner.set_holder(Some(to), &self.tx);
+                Ok(())
+            }
+            Some(_) => Err("viewers cannot take the keyboard"),
+            None => Err("no such client"),
+        }
+    }
+
+    pub fn deny_control(&self, id: u64, to: u64) -> Result<(), &'static str> {
+        if self.holder() != Some(id) {
+            return Err("only the client holding the keyboard can deny it");
+        }
+        let _ = self.tx.send(Presence::ControlDenied(to));
+        Ok(())
+    }
+}
+
+impl Clients {
+    fn set_holder(&mut self, holder: Option<u64>, tx: &broadcast::Sender<Presence>) {
+        self.holder = holder;
+        let _ = tx.send(Presence::Control(holder));
+    }
+
+    fn is_idle(&self, id: u64) -> bool {
+        self.clients
+            .get(&id)
+            .is_none_or(|info| unix_millis().saturating_sub(info.last_active) > CONTROL_IDLE_MS)
+    }
+}
+
+pub struct Registration {
+    registry: Arc<ClientRegistry>,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut inner = self.registry.inner.lock().unwrap();
+        inner.clients.remove(&self.id);
+        let _ = self.registry.tx.send(Presence::Leave(self.id));
+        if inner.holder == Some(self.id) {
+            inner.set_holder(None, &self.registry.tx);
+        }
+    }
+}
@@ -1,100 +1,570 @@
-use crate::models::{AppState, logger};
+use crate::models::layout::Remapper;
+use crate::models::{AppConfig, AppState, ProfileQuery, Role, TokenQuery, buf_trim, logger};
 use axum::{
     extract::{
-        Extension,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Extension, Query,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode, header::USER_AGENT},
+    response::{IntoResponse, Response},
 };
 use bytes::Bytes;
-use std::sync::Arc;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, atomic::Ordering};
 use tokio::select;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
 
-use crate::models::ClientMsg;
+use super::compress::{Codec, CompressionMode};
+use super::presence::Presence;
+use crate::config::validate::ConfigIssue;
+use crate::models::{BINARY_PROTOCOL, ClientMsg, FEATURES, PROTOCOL_VERSION, ProtoError};
+use crate::pty::FlowHandle;
+use crate::pty::terminal::Terminal;
 
-pub async fn ws_handler(ws: WebSocketUpgrade, Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| client_session(socket, state))
+const MAX_MARKER_LEN: usize = 256;
+// flow control watermarks on bytes sent but not yet acked
+const FLOW_HIGH: u64 = 512 * 1024;
+const FLOW_LOW: u64 = 128 * 1024;
+// application close code for a client that stopped answering (mirrors HTTP 408)
+const CLOSE_TIMEOUT: u16 = 4408;
+// the terminal's profile was removed from config.toml (mirrors HTTP 410)
+const CLOSE_PROFILE_REMOVED: u16 = 4410;
+
+/// Per-connection protocol state.
+struct Conn {
+    client: u64,
+    term: Arc<Terminal>,
+    role: Role,
+    binary: bool,
+    // set once the client opts into ack-based flow control in its hello
+    flow_control: bool,
+    unacked: u64,
+    flow: FlowHandle,
+    // effective terminal size, after arbitration between clients
+    size_rx: watch::Receiver<(u16, u16)>,
+    // requested in the hello; switched on once pending output has gone out uncompressed
+    compress_req: Option<CompressionMode>,
+    codec: Option<Codec>,
+    // the layout applied to this client's input, for clients that asked for `remap`
+    remap: Option<Remapper>,
+}
+
+impl Conn {
+    /// Encodes one output frame; flow control keeps counting uncompressed bytes.
+    fn encode(&mut self, chunk: &[u8]) -> anyhow::Result<Bytes> {
+        match &mut self.codec {
+            Some(codec) => Ok(Bytes::from(codec.encode(chunk)?)),
+            None => Ok(Bytes::copy_from_slice(chunk)),
+        }
+    }
+
+    fn sent(&mut self, n: usize) {
+        self.unacked += n as u64;
+        if self.flow_control && self.unacked > FLOW_HIGH {
+            self.flow.set_paused(true);
+        }
+    }
+
+    fn acked(&mut self, n: u64) {
+        self.unacked = self.unacked.saturating_sub(n);
+        if self.unacked <= FLOW_LOW {
+            self.flow.set_paused(false);
+        }
+    }
+}
+
+/// Who is on the other end, for the client registry.
+struct Peer {
+    addr: SocketAddr,
+    user_agent: Option<String>,
+}
+
+impl Peer {
+    fn new(addr: SocketAddr, headers: &HeaderMap) -> Self {
+        let user_agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_owned);
+        Self { addr, user_agent }
+    }
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(params): Query<TokenQuery>,
+    Query(profile): Query<ProfileQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    let role = state.auth.read().unwrap().role(params.token.as_deref());
+    let Some(role) = role else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    // writers start a profile's terminal if nobody has yet; viewers only join running ones
+    let opened = match role {
+        Role::Write => state.terminals.open(profile.name()).await,
+        Role::View => Ok(state.terminals.get(profile.name()).await),
+    };
+    let term = match opened {
+        Ok(Some(term)) => term,
+        Ok(None) => return (StatusCode::NOT_FOUND, format!("no terminal '{}'", profile.name())).into_response(),
+        Err(e) => {
+            logger(
+                "error",
+                format!("Failed to start terminal '{}': {:#}", profile.name(), e),
+            );
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    upgrade(ws, state, term, role, Peer::new(addr, &headers))
+}
+
+/// Same session, but whatever the token grants, the client can only watch.
+pub async fn ws_handler_view(
+    ws: WebSocketUpgrade,
+    Query(params): Query<TokenQuery>,
+    Query(profile): Query<ProfileQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    let role = state.auth.read().unwrap().role(params.token.as_deref());
+    if role.is_none() {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    // viewers only watch terminals that are already running
+    match state.terminals.get(profile.name()).await {
+        Some(term) => upgrade(ws, state, term, Role::View, Peer::new(addr, &headers)),
+        None => (StatusCode::NOT_FOUND, format!("no terminal '{}'", profile.name())).into_response(),
+    }
 }
 
-async fn client_session(mut socket: WebSocket, state: Arc<AppState>) {
-    let (mut rx, history) = state.pty.subscribe().await;
-    if let Err(e) = socket.send(Message::Binary(Bytes::from(history.to_vec()))).await {
+fn upgrade(ws: WebSocketUpgrade, state: Arc<AppState>, term: Arc<Terminal>, role: Role, peer: Peer) -> Response {
+    let ws = ws.protocols([BINARY_PROTOCOL]);
+    let binary = ws.selected_protocol().is_some();
+    ws.on_upgrade(move |socket| client_session(socket, state, term, binary, role, peer))
+}
+
+/// Parses a client message; binary frames are JSON unless the binary subprotocol was negotiated.
+pub(crate) fn parse_binary(bin: &[u8], binary: bool) -> Result<ClientMsg, ProtoError> {
+    match binary {
+        true => ClientMsg::from_frame(bin),
+        false => ClientMsg::from_json(bin),
+    }
+}
+
+async fn send_event(sock: &mut WebSocket, event: &str, value: impl Serialize) -> anyhow::Result<()> {
+    let payload = serde_json::json!({
+        "event": event,
+        "value": value
+    });
+    sock.send(Message::from(payload.to_string())).await?;
+    Ok(())
+}
+
+async fn client_session(
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+    term: Arc<Terminal>,
+    binary: bool,
+    role: Role,
+    peer: Peer,
+) {
+    let client = state.next_client.fetch_add(1, Ordering::Relaxed);
+    let mut presence_rx = term.clients.subscribe();
+    let peers: Vec<_> = term.clients.list();
+    let _registration = term.clients.join(client, peer.addr, peer.user_agent, role);
+    let (rows, cols) = *term.stty_size.read().await;
+    let hello = serde_json::json!({
+        "server": concat!("xterm-rs/", env!("CARGO_PKG_VERSION")),
+        "protocol": PROTOCOL_VERSION,
+        "session": term.session,
+        "profile": term.profile,
+        "client": client,
+        "role": role,
+        "peers": peers,
+        "control": term.clients.holder(),
+        "framing": if binary { "binary" } else { "json" },
+        "features": FEATURES,
+        "size": { "rows": rows, "cols": cols },
+    });
+    if send_event(&mut socket, "hello", hello).await.is_err() {
+        return;
+    }
+
+    let mut conn = Conn {
+        client,
+        term: Arc::clone(&term),
+        role,
+        binary,
+        flow_control: false,
+        unacked: 0,
+        flow: term.pty.flow_handle(),
+        size_rx: term.sizes.subscribe(),
+        compress_req: None,
+        codec: None,
+        remap: None,
+    };
+
+    let (mut rx, history) = term.pty.subscribe().await;
+    let history = history.to_vec();
+    let len = history.len();
+    if let Err(e) = socket.send(Message::Binary(Bytes::from(history))).await {
         logger("error", format!("Failed to send history: {}", e));
         return;
     }
+    conn.sent(len);
 
+    term.caster
+        .marker(term.start.elapsed().as_secs_f32(), "client connected");
+
+    let mut closed_rx = term.closed.subscribe();
     let mut cfg_rx = state.watcher.subscribe();
-    let cfg = state.watcher.current();
-    let payload = serde_json::json!({
-        "event": "config",
-        "value": cfg
-    });
-    let _ = socket.send(Message::from(payload.to_string())).await;
+    let cfg = cfg_rx.borrow_and_update().clone().for_profile(&term.profile);
+    let _ = send_event(&mut socket, "layout", layout_event(&cfg)).await;
+    let _ = send_event(&mut socket, "config", cfg).await;
+    let mut issues_rx = state.watcher.issues();
+    let issues = issues_rx.borrow_and_update().all();
+    if !issues.is_empty() {
+        let _ = send_event(&mut socket, "config-error", issues).await;
+    }
+
+    // output is batched and sent at most max_fps times a second
+    let mut pending: Vec<u8> = Vec::new();
+    let mut frame_tick = time::interval(Duration::from_secs(1) / state.output.max_fps);
+    frame_tick.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    // pings carry the send time so the pong gives the round trip; any frame from the client counts as alive
+    let mut keepalive_rx = state.keepalive.subscribe();
+    let mut keepalive = keepalive_rx.borrow_and_update().clone();
+    let mut ping_tick = time::interval(keepalive.interval);
+    ping_tick.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    let mut last_seen = Instant::now();
 
     loop {
         select! {
-            Ok(bytes) = rx.recv() => {
-                socket.send(Message::Binary(Bytes::copy_from_slice(&bytes))).await.ok();
-                if let Some(caster) = &state.caster {
-                    caster.output(state.start.elapsed().as_secs_f32(), bytes.to_vec());
+            res = rx.recv(), if !conn.flow.is_paused() => match res {
+                Ok(bytes) => {
+                    pending.extend_from_slice(&bytes);
+                    if pending.len() >= state.output.max_frame {
+                        flush_output(&mut socket, &state, &mut conn, &mut rx, &mut pending).await;
+                    }
+                }
+                // fell too far behind the other clients: reset the terminal and replay history
+                Err(RecvError::Lagged(_)) => {
+                    let (new_rx, history) = term.pty.subscribe().await;
+                    rx = new_rx;
+                    pending = b"\x1bc".to_vec();
+                    pending.extend(history.to_vec());
+                    conn.unacked = 0;
+                    flush_output(&mut socket, &state, &mut conn, &mut rx, &mut pending).await;
+                }
+                Err(RecvError::Closed) => break,
+            },
+
+            _ = frame_tick.tick(), if !pending.is_empty() => {
+                flush_output(&mut socket, &state, &mut conn, &mut rx, &mut pending).await;
+            }
+
+            _ = ping_tick.tick() => {
+                let sent = state.start.elapsed().as_micros() as u64;
+                if socket.send(Message::Ping(Bytes::copy_from_slice(&sent.to_le_bytes()))).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok(()) = keepalive_rx.changed() => {
+                keepalive = keepalive_rx.borrow_and_update().clone();
+                ping_tick = time::interval(keepalive.interval);
+                ping_tick.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+            }
+
+            _ = time::sleep_until(last_seen + keepalive.timeout) => {
+                logger("info", format!("Client {} timed out after {:?}", client, keepalive.timeout));
+                let close = CloseFrame {
+                    code: CLOSE_TIMEOUT,
+                    reason: "heartbeat timeout".into(),
+                };
+                let _ = socket.send(Message::Close(Some(close))).await;
+                break;
+            }
+
+            // the profile went away: what is left of the output, then a close
+            Ok(()) = async { closed_rx.wait_for(|closed| *closed).await.map(drop) } => {
+                while let Ok(bytes) = rx.try_recv() {
+                    pending.extend_from_slice(&bytes);
+                }
+                flush_output(&mut socket, &state, &mut conn, &mut rx, &mut pending).await;
+                let close = CloseFrame {
+                    code: CLOSE_PROFILE_REMOVED,
+                    reason: "profile removed".into(),
+                };
+                let _ = socket.send(Message::Close(Some(close))).await;
+                break;
+            }
+
+            Ok(()) = conn.size_rx.changed() => {
+                let (rows, cols) = *conn.size_rx.borrow_and_update();
+                let _ = send_event(&mut socket, "size", serde_json::json!({ "rows": rows, "cols": cols })).await;
+            }
+
+            res = presence_rx.recv() => {
+                let sent = match res {
+                    Ok(Presence::Join(info)) if info.id != client => send_event(&mut socket, "join", info).await,
+                    Ok(Presence::Leave(id)) if id != client => {
+                        send_event(&mut socket, "leave", serde_json::json!({ "id": id })).await
+                    }
+                    Ok(Presence::Control(holder)) => {
+                        send_event(&mut socket, "control", serde_json::json!({ "holder": holder })).await
+                    }
+                    Ok(Presence::ControlRequest { from, to }) if to == client => {
+                        send_event(&mut socket, "control-request", serde_json::json!({ "from": from })).await
+                    }
+                    Ok(Presence::ControlDenied(to)) if to == client => {
+                        send_event(&mut socket, "control-denied", serde_json::json!({})).await
+                    }
+                    _ => Ok(()),
+                };
+                if sent.is_err() {
+                    break;
                 }
             }
 
             Ok(()) = cfg_rx.changed() => {
-                let cfg = cfg_rx.borrow().clone();
-                let payload = serde_json::json!({
-                    "event": "config",
-                    "value": cfg
-                });
-                let _ = socket.send(Message::from(payload.to_string())).await;
+                let cfg = cfg_rx.borrow().clone().for_profile(&term.profile);
+                if let Some(remap) = &mut conn.remap {
+                    remap.set_layout(&cfg.keymap());
+                }
+                let _ = send_event(&mut socket, "layout", layout_event(&cfg)).await;
+                let _ = send_event(&mut socket, "config", cfg).await;
+            }
+
+            // an empty list means the file is fine again
+            Ok(()) = issues_rx.changed() => {
+                let issues = issues_rx.borrow_and_update().all();
+                let _ = send_event(&mut socket, "config-error", issues).await;
             }
 
             msg = socket.recv() => {
-                match msg {
-                    Some(Ok(Message::Text(txt))) => {
-                        if let Ok(cmd) = serde_json::from_str::<ClientMsg>(&txt) {
-                            if handle(cmd, &state, &mut socket).await.is_err() {
-                                break;
-                            }
-                        }
-                    }
-                    Some(Ok(Message::Binary(bin))) => {
-                        if let Ok(cmd) = serde_json::from_slice::<ClientMsg>(&bin) {
-                            if handle(cmd, &state, &mut socket).await.is_err() {
-                                break;
-                            }
+                last_seen = Instant::now();
+                let parsed = match msg {
+                    Some(Ok(Message::Text(txt))) => ClientMsg::from_json(txt.as_bytes()),
+                    Some(Ok(Message::Binary(bin))) => parse_binary(&bin, conn.binary),
+                    Some(Ok(Message::Pong(payload))) => {
+                        if let Ok(sent) = <[u8; 8]>::try_from(&payload[..]) {
+                            let now = state.start.elapsed().as_micros() as u64;
+                            let rtt = now.saturating_sub(u64::from_le_bytes(sent));
+                            logger("rtt", serde_json::json!({ "client": client, "ms": rtt as f64 / 1000.0 }));
                         }
+                        continue;
                     }
                     Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
-                    _ => {}
+                    _ => continue,
+                };
+                let res = match parsed {
+                    Ok(cmd) => handle(cmd, &state, &mut socket, &mut conn).await,
+                    Err(e) => send_event(&mut socket, "error", e).await,
+                };
+                if res.is_err() {
+                    break;
+                }
+                if let Some(mode) = conn.compress_req.take() {
+                    flush_output(&mut socket, &state, &mut conn, &mut rx, &mut pending).await;
+                    if start_compression(&mut socket, &mut conn, mode).await.is_err() {
+                        break;
+                    }
                 }
             }
         }
     }
+
+    term.caster
+        .marker(term.start.elapsed().as_secs_f32(), "client disconnected");
+    if let Some((rows, cols)) = term.sizes.remove(client)
+        && let Err(e) = resize_pty(&term, rows, cols).await
+    {
+        logger("error", format!("Failed to resize pty: {}", e));
+    }
 }
 
-async fn handle(msg: ClientMsg, state: &AppState, sock: &mut WebSocket) -> anyhow::Result<()> {
-    match msg {
-        ClientMsg::Data { value } => {
-            if let Some(caster) = &state.caster {
-                caster.input(state.start.elapsed().as_secs_f32(), value.as_bytes().to_vec());
+/// Sends the batched output in frames of at most `max_frame` bytes.
+/// With `skip_behind` set, a client that has fallen far behind only gets the latest screen.
+async fn flush_output(
+    sock: &mut WebSocket,
+    state: &AppState,
+    conn: &mut Conn,
+    rx: &mut broadcast::Receiver<Vec<u8>>,
+    pending: &mut Vec<u8>,
+) {
+    if let Some(limit) = state.output.skip_behind {
+        while let Ok(bytes) = rx.try_recv() {
+            pending.extend_from_slice(&bytes);
+        }
+        if pending.len() > limit {
+            let (rows, cols) = *conn.term.stty_size.read().await;
+            let idx = buf_trim(pending, cols, rows as u32);
+            // the cut loses attributes, alt screen and scroll region (and may split a sequence): start clean
+            if idx > 0 {
+                pending.splice(..idx, *b"\x1bc");
+            }
+        }
+    }
+
+    for chunk in pending.chunks(state.output.max_frame) {
+        let frame = match conn.encode(chunk) {
+            Ok(frame) => frame,
+            Err(e) => {
+                logger("error", format!("Failed to compress output: {}", e));
+                break;
             }
-            state.pty.write(value.as_bytes()).await?;
+        };
+        if sock.send(Message::Binary(frame)).await.is_err() {
+            break;
         }
+        conn.sent(chunk.len());
+    }
+    pending.clear();
+}
+
+/// Announces the codec, then compresses every later output frame.
+async fn start_compression(sock: &mut WebSocket, conn: &mut Conn, mode: CompressionMode) -> anyhow::Result<()> {
+    send_event(sock, "compression", serde_json::json!({ "mode": mode })).await?;
+    conn.codec = Some(Codec::new(mode));
+    Ok(())
+}
+
+async fn handle(msg: ClientMsg, state: &AppState, sock: &mut WebSocket, conn: &mut Conn) -> anyhow::Result<()> {
+    let term = Arc::clone(&conn.term);
+    // viewers may only annotate and keep the connection alive
+    let writes = !matches!(
+        msg,
+        ClientMsg::Marker { .. } | ClientMsg::Heartbeat | ClientMsg::Hello { .. } | ClientMsg::Ack { .. }
+    );
+    if conn.role == Role::View && writes {
+        let err = ProtoError {
+            code: "read-only",
+            message: "this connection can only view the terminal".into(),
+        };
+        return send_event(sock, "error", err).await;
+    }
+    if !matches!(msg, ClientMsg::Heartbeat | ClientMsg::Ack { .. }) {
+        term.clients.touch(conn.client);
+    }
+    match msg {
+        ClientMsg::Data { value } => input(sock, conn, value.into_bytes()).await?,
+        ClientMsg::Input(bytes) => input(sock, conn, bytes).await?,
         ClientMsg::Resize { value } => {
-            if let Some(caster) = &state.caster {
-                caster.resize(state.start.elapsed().as_secs_f32(), value.rows, value.cols);
+            if let Some((rows, cols)) = term.sizes.update(conn.client, value.rows, value.cols) {
+                resize_pty(&term, rows, cols).await?;
             }
-            state.pty.resize(value.rows, value.cols).await?;
-            let mut sz = state.stty_size.write().await;
-            *sz = (value.rows, value.cols);
+            // the requester always learns the outcome, so it can letterbox when it lost
+            let (rows, cols) = *conn.size_rx.borrow_and_update();
+            send_event(sock, "size", serde_json::json!({ "rows": rows, "cols": cols })).await?;
         }
-        ClientMsg::Heartbeat => {
-            if let Some(caster) = &state.caster {
-                caster.heartbeat();
+        ClientMsg::Marker { value } => {
+            let mut end = value.len().min(MAX_MARKER_LEN);
+            while !value.is_char_boundary(end) {
+                end -= 1;
             }
+            term.caster.marker(term.start.elapsed().as_secs_f32(), &value[..end]);
+        }
+        ClientMsg::Heartbeat => {
+            term.caster.heartbeat();
             sock.send(Message::Text(r#"{"event":"heartbeat-pong"}"#.into())).await?;
         }
+        ClientMsg::Hello { value } => {
+            if value.protocol != PROTOCOL_VERSION {
+                let err = ProtoError {
+                    code: "protocol-mismatch",
+                    message: format!(
+                        "client speaks protocol {}, server {}; reload to update",
+                        value.protocol, PROTOCOL_VERSION
+                    ),
+                };
+                send_event(sock, "error", err).await?;
+            }
+            let missing: Vec<_> = value
+                .features
+                .iter()
+                .filter(|f| !FEATURES.contains(&f.as_str()))
+                .collect();
+            if !missing.is_empty() {
+                let err = ProtoError::unsupported(format!("features not available: {:?}", missing));
+                send_event(sock, "error", err).await?;
+            }
+            conn.flow_control = value.features.iter().any(|f| f == "flow-control");
+            if value.features.iter().any(|f| f == "remap") {
+                let mut remap = Remapper::default();
+                remap.set_layout(&state.watcher.current().keymap());
+                conn.remap = Some(remap);
+            }
+            if conn.codec.is_none() {
+                conn.compress_req = value.compression;
+            }
+        }
+        ClientMsg::Ack { value } => conn.acked(value),
+        ClientMsg::RequestControl => term.clients.request_control(conn.client),
+        ClientMsg::ReleaseControl => term.clients.release_control(conn.client),
+        ClientMsg::GrantControl { value } => {
+            if let Err(e) = term.clients.grant_control(conn.client, value) {
+                send_event(sock, "error", control_error(e)).await?;
+            }
+        }
+        ClientMsg::DenyControl { value } => {
+            if let Err(e) = term.clients.deny_control(conn.client, value) {
+                send_event(sock, "error", control_error(e)).await?;
+            }
+        }
+        ClientMsg::SetConfig { value } => match state.watcher.update(&value).await {
+            Ok(()) => logger("info", format!("Client {} updated config: {}", conn.client, value)),
+            Err(issues) => send_event(sock, "error", config_rejected(&issues)).await?,
+        },
+    }
+    Ok(())
+}
+
+async fn resize_pty(term: &Terminal, rows: u16, cols: u16) -> anyhow::Result<()> {
+    term.caster.resize(term.start.elapsed().as_secs_f32(), rows, cols);
+    term.pty.resize(rows, cols).await?;
+    *term.stty_size.write().await = (rows, cols);
+    Ok(())
+}
+
+/// The layout keys are remapped with, resolved so clients need no tables of their own.
+fn layout_event(cfg: &AppConfig) -> serde_json::Value {
+    serde_json::json!({ "name": cfg.layout, "keys": cfg.keymap() })
+}
+
+fn config_rejected(issues: &[ConfigIssue]) -> ProtoError {
+    ProtoError {
+        code: "invalid-config",
+        message: issues.iter().map(|i| i.message.as_str()).collect::<Vec<_>>().join("; "),
+    }
+}
+
+fn control_error(message: &str) -> ProtoError {
+    ProtoError {
+        code: "no-control",
+        message: message.into(),
+    }
+}
+
+/// Writes to the PTY if this client holds the keyboard (taking it when nobody does).
+async fn input(sock: &mut WebSocket, conn: &mut Conn, bytes: Vec<u8>) -> anyhow::Result<()> {
+    if !conn.term.clients.try_input(conn.client) {
+        return send_event(
+            sock,
+            "error",
+            control_error("another client holds the keyboard; request control"),
+        )
+        .await;
     }
+    let bytes = match &mut conn.remap {
+        Some(remap) => remap.remap(&bytes),
+        None => bytes,
+    };
+    conn.term.pty.write(&bytes).await?;
+    conn.term.caster.input(conn.term.start.elapsed().as_secs_f32(), bytes);
     Ok(())
 }
@@ -1,12 +1,15 @@
-use crate::models::AppState;
-use crate::models::ClientMsg;
+use super::socket_handler::parse_binary;
+use crate::models::{AppState, Role, TokenQuery};
+use crate::models::{BINARY_PROTOCOL, ClientMsg};
 use crate::pty::PtyManager;
+use crate::pty::terminal::DEFAULT_PROFILE;
 use axum::{
     extract::{
-        Extension,
+        Extension, Query,
         ws::{CloseFrame, Message, Utf8Bytes, WebSocket, WebSocketUpgrade},
     },
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 
 use bytes::Bytes;
@@ -14,15 +17,33 @@ use std::sync::Arc;
 
 use tokio::select;
 
-pub async fn ws_handler_debug(ws: WebSocketUpgrade, Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
-    let size_lock = Arc::clone(&state.stty_size);
+/// A fresh writable shell, so it needs the writer token when tokens are configured.
+pub async fn ws_handler_debug(
+    ws: WebSocketUpgrade,
+    Query(params): Query<TokenQuery>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    let role = state.auth.read().unwrap().role(params.token.as_deref());
+    match role {
+        Some(Role::Write) => {}
+        Some(Role::View) => return StatusCode::FORBIDDEN.into_response(),
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    }
+    // a private shell like the default terminal's, at its current size
+    let Some(term) = state.terminals.get(DEFAULT_PROFILE).await else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let size_lock = Arc::clone(&term.stty_size);
+    let spec = term.pty.spec();
+    let ws = ws.protocols([BINARY_PROTOCOL]);
+    let binary = ws.selected_protocol().is_some();
 
     ws.on_upgrade(move |mut socket| async move {
         let (rows, cols) = *size_lock.read().await;
-        match PtyManager::new(rows, cols, 0).await {
+        match PtyManager::new(rows, cols, 0, spec).await {
             Ok(new_pty) => {
                 let pty = Arc::new(new_pty);
-                debug_session(socket, pty).await;
+                debug_session(socket, pty, binary).await;
             }
             Err(e) => {
                 let _ = socket
@@ -36,7 +57,7 @@ pub async fn ws_handler_debug(ws: WebSocketUpgrade, Extension(state): Extension<
     })
 }
 
-async fn debug_session(mut socket: WebSocket, pty: Arc<PtyManager>) {
+async fn debug_session(mut socket: WebSocket, pty: Arc<PtyManager>, binary: bool) {
     let (mut rx, history) = pty.subscribe().await;
     let _ = socket.send(Message::Binary(Bytes::from(history.to_vec()))).await;
 
@@ -48,12 +69,12 @@ async fn debug_session(mut socket: WebSocket, pty: Arc<PtyManager>) {
 
             msg = socket.recv() => match msg {
                 Some(Ok(Message::Text(txt))) => {
-                    if let Ok(cmd) = serde_json::from_str::<ClientMsg>(&txt) {
+                    if let Ok(cmd) = ClientMsg::from_json(txt.as_bytes()) {
                         apply_cmd(cmd, &pty).await;
                     }
                 }
                 Some(Ok(Message::Binary(bin))) => {
-                    if let Ok(cmd) = serde_json::from_slice::<ClientMsg>(&bin) {
+                    if let Ok(cmd) = parse_binary(&bin, binary) {
                         apply_cmd(cmd, &pty).await;
                     }
                 }
@@ -69,6 +90,9 @@ async fn apply_cmd(cmd: ClientMsg, pty: &PtyManager) {
         ClientMsg::Data { value } => {
             let _ = pty.write(value.as_bytes()).await;
         }
+        ClientMsg::Input(bytes) => {
+            let _ = pty.write(&bytes).await;
+        }
         ClientMsg::Resize { value } => {
             let _ = pty.resize(value.rows, value.cols).await;
         }
@@ -1,37 +1,6 @@
-export const mapColemak = {
-    KeyQ: "q",
-    KeyW: "w",
-    KeyE: "f",
-    KeyR: "p",
-    KeyT: "g",
-    KeyY: "j",
-    KeyU: "l",
-    KeyI: "u",
-    KeyO: "y",
-    KeyP: ";",
-    KeyA: "a",
-    KeyS: "r",
-    KeyD: "s",
-    KeyF: "t",
-    KeyG: "d",
-    KeyH: "h",
-    KeyJ: "n",
-    KeyK: "e",
-    KeyL: "i",
-    Semicolon: "o",
-    KeyZ: "z",
-    KeyX: "x",
-    KeyC: "c",
-    KeyV: "v",
-    KeyB: "b",
-    KeyN: "k",
-    KeyM: "m",
-};
-const layouts = { qwerty: null, colemak: mapColemak };
-
-const shifted = { ";": ":", ":": ":" };
-
-export function makeKeyHandler(socket, getLayout) {
+// Keys are remapped with the layout the server sends in its `layout` event:
+// { KeyboardEvent.code: [unshifted, shifted] }. Keys not listed type what the OS says.
+export function makeKeyHandler(socket, getKeymap) {
     let swallowNextKeypress = false;
 
     return function handleKey(ev) {
@@ -46,22 +15,17 @@ export function makeKeyHandler(socket, getLayout) {
 
         if (ev.ctrlKey || ev.altKey || ev.metaKey) return true;
 
-        const table = layouts[getLayout()] ?? null;
-        const base = table ? table[ev.code] : null;
-        if (!base) return true;
+        const keys = getKeymap()[ev.code];
+        if (!keys) return true;
 
-        socket.send(JSON.stringify({ event: "data", value: buildSeq(ev, base) }));
+        socket.send(JSON.stringify({ event: "data", value: buildSeq(ev, keys) }));
         swallowNextKeypress = true;
         return false;
     };
 }
 
-function buildSeq(e, base) {
-    const caps = e.getModifierState("CapsLock");
-    const wantUpper = caps ? !e.shiftKey : e.shiftKey;
-    const upper = base.toUpperCase();
-    const lower = base.toLowerCase();
-    const withShift = shifted[upper] ?? upper;
-
-    return wantUpper ? withShift : lower;
+function buildSeq(e, [base, shifted]) {
+    // Caps Lock only affects letters, i.e. keys whose shifted form is just the uppercase
+    const caps = e.getModifierState("CapsLock") && shifted === base.toUpperCase() && base !== shifted;
+    return e.shiftKey !== caps ? shifted : base;
 }